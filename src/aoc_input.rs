@@ -39,10 +39,10 @@ fn try_get_input(day: u8) -> Result<String, Box<dyn Error>> {
     let _ = std::fs::create_dir(path.parent().unwrap());
 
     if path.exists() {
-        println!("Cache hit for day {}", day);
+        eprintln!("Cache hit for day {}", day);
         Ok(std::fs::read_to_string(path)?)
     } else {
-        println!("Cache miss for day {}", day);
+        eprintln!("Cache miss for day {}", day);
         let input_str = get_input_web(day)?;
         std::fs::write(path, &input_str)?;
         Ok(input_str)
@@ -1,7 +1,9 @@
+use std::cell::RefCell;
 use std::cmp::{max, min};
 use std::collections::HashMap;
 use std::convert::From;
 use std::ops::{Add, AddAssign};
+use std::rc::Rc;
 #[macro_use]
 extern crate num_derive;
 use aoc2019::aoc_input::get_input;
@@ -85,75 +87,98 @@ impl AddAssign<Delta> for Coordinate {
     }
 }
 
-enum RobotRunResult {
-    Done,
-    Paint(PanelColor),
-}
-
 struct Robot {
-    brain: IntcodeMachine,
     location: Coordinate,
     direction: Direction,
+    paint_request: Option<PanelColor>,
+    /// A panel the robot has left, to be painted when the brain next looks at the camera.
+    pending_paint: Option<(Coordinate, PanelColor)>,
 }
 
 impl Robot {
-    fn new(program: Tape) -> Robot {
+    fn new() -> Robot {
         Robot {
-            brain: IntcodeMachine::new(program),
             location: Coordinate::origin(),
             direction: Direction::Up,
+            paint_request: None,
+            pending_paint: None,
         }
     }
+}
 
-    fn feed_input(&mut self, value: isize) {
-        self.brain.input.borrow_mut().push_back(value);
-    }
+struct Board {
+    grid: HashMap<Coordinate, PanelColor>,
+    robot: Robot,
+}
 
-    fn read_output(&mut self) -> isize {
-        self.brain.output.borrow_mut().pop_front().unwrap()
-    }
+type BoardRef = Rc<RefCell<Board>>;
 
-    fn step(&mut self, location_color: PanelColor) -> RobotRunResult {
-        self.feed_input(location_color.to_isize().unwrap());
+/// Reports the color of the panel under the robot whenever the brain asks for it. Every
+/// panel the camera looks at counts as visited, and a paint only lands once the brain reads
+/// again, so instructions given just before it halts are dropped.
+struct Camera {
+    board: BoardRef,
+}
 
-        match self.brain.run().unwrap() {
-            StopStatus::Halted => RobotRunResult::Done,
-            StopStatus::BlockedOnInput => {
-                let paint_request = self.read_output();
-                let paint_request = PanelColor::from_isize(paint_request).unwrap();
-                let turn = self.read_output();
+/// Turns and moves the robot as the brain produces paint-then-turn output pairs, leaving the
+/// paint for the camera to apply.
+struct Actuator {
+    board: BoardRef,
+}
 
-                self.direction = self.direction.turn(Turn::from_isize(turn).unwrap());
-                self.location += self.direction.into();
-                RobotRunResult::Paint(paint_request)
-            }
+impl IntcodeInput for Camera {
+    fn read(&mut self) -> Option<isize> {
+        let mut board = self.board.borrow_mut();
+        if let Some((location, color)) = board.robot.pending_paint.take() {
+            board.grid.insert(location, color);
         }
+        let location = board.robot.location;
+        let color = *board.grid.entry(location).or_insert(PanelColor::Black);
+        color.to_isize()
     }
 }
 
-struct Board {
-    grid: HashMap<Coordinate, PanelColor>,
-    robot: Robot,
+impl IntcodeOutput for Actuator {
+    fn write(&mut self, value: isize) {
+        let mut board = self.board.borrow_mut();
+        match board.robot.paint_request.take() {
+            None => board.robot.paint_request = Some(PanelColor::from_isize(value).unwrap()),
+            Some(color) => {
+                let robot = &mut board.robot;
+                robot.pending_paint = Some((robot.location, color));
+                robot.direction = robot.direction.turn(Turn::from_isize(value).unwrap());
+                robot.location += robot.direction.into();
+            }
+        }
+    }
 }
 
 impl Board {
-    fn new(robot: Robot, origin_color: PanelColor) -> Board {
+    fn new(origin_color: PanelColor) -> Board {
         let mut board = Board {
             grid: HashMap::new(),
-            robot,
+            robot: Robot::new(),
         };
         board.grid.insert(Coordinate::origin(), origin_color);
         board
     }
 
-    fn run_robot(&mut self) {
-        loop {
-            let entry = self.grid.entry(self.robot.location);
-            let current_panel = entry.or_insert(PanelColor::Black);
-            match self.robot.step(*current_panel) {
-                RobotRunResult::Done => break,
-                RobotRunResult::Paint(new_color) => *current_panel = new_color,
-            }
+    fn run_robot(self, program: Tape) -> Board {
+        let board = Rc::new(RefCell::new(self));
+        let camera = Camera {
+            board: board.clone(),
+        };
+        let actuator = Actuator {
+            board: board.clone(),
+        };
+
+        let mut brain = IntcodeMachine::new_io(program, camera, actuator);
+        brain.run_to_completion().unwrap();
+        drop(brain);
+
+        match Rc::try_unwrap(board) {
+            Ok(board) => board.into_inner(),
+            Err(_) => unreachable!("the brain was the only other owner"),
         }
     }
 
@@ -194,13 +219,10 @@ impl Board {
 
 fn main() {
     let program = parse_intcode_program(&get_input(11));
-    let mut board = Board::new(Robot::new(program.clone()), PanelColor::Black);
-
-    board.run_robot();
+    let board = Board::new(PanelColor::Black).run_robot(program.clone());
     println!("Painted panels: {}", board.painted_panels());
 
-    let mut board = Board::new(Robot::new(program), PanelColor::White);
-    board.run_robot();
+    let board = Board::new(PanelColor::White).run_robot(program);
     println!(
         "Grid when origin starts colored white:\n{}",
         board.render_grid()
@@ -1,12 +1,14 @@
-use itertools::Itertools;
 use std::collections::HashMap;
 #[macro_use]
 extern crate num_derive;
 use aoc2019::aoc_input::get_input;
+use aoc2019::intcode::replay::{replay, Recorder, Recording};
 use aoc2019::intcode::*;
 use num_traits::FromPrimitive;
+use std::cell::RefCell;
 use std::cmp::max;
 use std::fmt;
+use std::rc::Rc;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive)]
 enum Tile {
@@ -21,7 +23,7 @@ type Coordinate = (isize, isize);
 
 #[derive(Debug)]
 struct SegmentDisplay {
-    input: StreamRef,
+    pending: Vec<isize>,
     score: isize,
     max_x: isize,
     max_y: isize,
@@ -29,9 +31,9 @@ struct SegmentDisplay {
 }
 
 impl SegmentDisplay {
-    fn new(input: StreamRef) -> Self {
+    fn new() -> Self {
         SegmentDisplay {
-            input,
+            pending: Vec::with_capacity(3),
             score: 0,
             max_x: 0,
             max_y: 0,
@@ -39,21 +41,26 @@ impl SegmentDisplay {
         }
     }
 
-    fn update(&mut self) {
-        for (x, y, b) in self.input.borrow_mut().drain(..).tuples() {
-            let coordinate = (x, y);
-            match coordinate {
-                (-1, 0) => {
-                    self.score = b;
-                }
-                _ => {
-                    self.max_x = max(self.max_x, x);
-                    self.max_y = max(self.max_y, y);
-                    let tile = Tile::from_isize(b).unwrap();
-                    self.blocks.insert(coordinate, tile);
-                }
-            };
+    fn draw(&mut self, value: isize) {
+        self.pending.push(value);
+        if self.pending.len() < 3 {
+            return;
         }
+
+        let (x, y, b) = (self.pending[0], self.pending[1], self.pending[2]);
+        self.pending.clear();
+        let coordinate = (x, y);
+        match coordinate {
+            (-1, 0) => {
+                self.score = b;
+            }
+            _ => {
+                self.max_x = max(self.max_x, x);
+                self.max_y = max(self.max_y, y);
+                let tile = Tile::from_isize(b).unwrap();
+                self.blocks.insert(coordinate, tile);
+            }
+        };
     }
 
     fn count_tiles_matching(&self, tile: Tile) -> usize {
@@ -92,52 +99,20 @@ impl fmt::Display for SegmentDisplay {
     }
 }
 
-#[derive(Debug)]
-struct Joystick {
-    output: StreamRef,
-}
-
-impl Joystick {
-    fn new(output: StreamRef) -> Self {
-        Joystick { output }
-    }
-
-    fn set(&mut self, direction: isize) {
-        self.output.borrow_mut().push_back(direction.signum());
-    }
-}
+type DisplayRef = Rc<RefCell<SegmentDisplay>>;
 
 #[derive(Debug)]
-struct ArcadeMachine {
-    machine: IntcodeMachine,
-    display: SegmentDisplay,
-    joystick: Joystick,
+struct DisplayDriver {
+    display: DisplayRef,
 }
 
-impl ArcadeMachine {
-    fn new(tape: Tape) -> Self {
-        let machine = IntcodeMachine::new(tape);
-        let display = SegmentDisplay::new(machine.output.clone());
-        let joystick = Joystick::new(machine.input.clone());
-        ArcadeMachine {
-            machine,
-            display,
-            joystick,
-        }
-    }
-
-    fn run(&mut self) -> StopStatus {
-        let status = self.machine.run().unwrap();
-        self.display.update();
-        status
-    }
-
-    fn run_to_completion(&mut self) {
-        self.machine.run_to_completion().unwrap();
-        self.display.update();
+impl IntcodeOutput for DisplayDriver {
+    fn write(&mut self, value: isize) {
+        self.display.borrow_mut().draw(value);
     }
 }
 
+#[derive(Debug)]
 struct Bot {
     current_ball_location: Coordinate,
     paddle_location: Coordinate,
@@ -162,11 +137,76 @@ impl Bot {
     }
 }
 
+#[derive(Debug)]
+enum Player {
+    Bot(Bot),
+    Human,
+}
+
+/// Answers the game's joystick reads on demand, consulting whoever is playing.
+#[derive(Debug)]
+struct Joystick {
+    display: DisplayRef,
+    player: Player,
+    show: bool,
+}
+
+impl IntcodeInput for Joystick {
+    fn read(&mut self) -> Option<isize> {
+        let display = self.display.borrow();
+        if self.show {
+            println!("{}", display);
+            if let Player::Bot(_) = self.player {
+                std::thread::sleep(std::time::Duration::from_secs(1));
+            }
+        }
+
+        let direction = match &mut self.player {
+            Player::Bot(bot) => bot.recommend_move(&display),
+            Player::Human => get_user_move(),
+        };
+        Some(direction.signum())
+    }
+}
+
+#[derive(Debug)]
+struct ArcadeMachine {
+    machine: IntcodeMachine<Joystick, DisplayDriver, Option<Recorder>>,
+    display: DisplayRef,
+}
+
+impl ArcadeMachine {
+    fn new(tape: Tape, player: Player, show: bool, record: bool) -> Self {
+        let display = Rc::new(RefCell::new(SegmentDisplay::new()));
+        let joystick = Joystick {
+            display: display.clone(),
+            player,
+            show,
+        };
+        let driver = DisplayDriver {
+            display: display.clone(),
+        };
+        ArcadeMachine {
+            machine: IntcodeMachine::new_io(tape, joystick, driver).with_hook(if record {
+                Some(Recorder::new())
+            } else {
+                None
+            }),
+            display,
+        }
+    }
+
+    fn run_to_completion(&mut self) {
+        self.machine.run_to_completion().unwrap();
+    }
+}
+
 fn count_block_tiles(tape: Tape) -> usize {
-    let mut arcade = ArcadeMachine::new(tape);
+    let mut arcade = ArcadeMachine::new(tape, Player::Bot(Bot::new()), false, false);
     arcade.run_to_completion();
 
-    arcade.display.count_tiles_matching(Tile::Block)
+    let display = arcade.display.borrow();
+    display.count_tiles_matching(Tile::Block)
 }
 
 fn get_user_move() -> isize {
@@ -182,35 +222,70 @@ fn get_user_move() -> isize {
     }
 }
 
-fn winning_score(mut tape: Tape, show: bool, interactive: bool) -> isize {
-    // Play for free
+fn free_play(mut tape: Tape) -> Tape {
     tape[0] = 2;
-    let mut arcade = ArcadeMachine::new(tape);
-    let mut bot = Bot::new();
-
-    while arcade.run() == StopStatus::BlockedOnInput {
-        if show {
-            println!("{}", arcade.display);
-            if !interactive {
-                std::thread::sleep(std::time::Duration::from_secs(1));
-            }
-        }
+    tape
+}
 
-        let direction = if interactive {
-            get_user_move()
-        } else {
-            bot.recommend_move(&arcade.display)
-        };
+fn winning_score(tape: Tape, show: bool, interactive: bool, record: Option<&str>) -> isize {
+    let tape = free_play(tape);
+    let player = if interactive {
+        Player::Human
+    } else {
+        Player::Bot(Bot::new())
+    };
 
-        arcade.joystick.set(direction);
+    let mut arcade = ArcadeMachine::new(tape, player, show, record.is_some());
+    arcade.run_to_completion();
+    if let (Some(path), Some(recorder)) = (record, &arcade.machine.hook) {
+        recorder
+            .recording
+            .save(path)
+            .expect("Failed writing recording");
     }
-    arcade.display.score
+
+    let display = arcade.display.borrow();
+    display.score
 }
 
+const USAGE: &str = "Usage: day13 [--play] [--record <file> | --replay <file>]";
+
+/// Instructions a replay may run past the last recorded event before it is given up on.
+const REPLAY_SLACK: usize = 1_000_000;
+
 fn main() {
     let input = get_input(13);
     let program = parse_intcode_program(&input);
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut interactive = false;
+    let mut record = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--play" => interactive = true,
+            "--record" => record = Some(args.next().expect(USAGE).as_str()),
+            "--replay" => {
+                let path = args.next().expect(USAGE);
+                let recording = Recording::load(path).expect("Failed reading recording");
+                let last = recording.events.last().map_or(0, |e| e.instruction);
+                match replay(free_play(program), &recording, last + REPLAY_SLACK) {
+                    Ok(instructions) => println!(
+                        "Replayed {} events over {} instructions",
+                        recording.events.len(),
+                        instructions
+                    ),
+                    Err(e) => println!("{}", e),
+                }
+                return;
+            }
+            _ => panic!("{}", USAGE),
+        }
+    }
+
     println!("Block tiles count: {}", count_block_tiles(program.clone()));
-    println!("Winning score: {}", winning_score(program, false, false));
+    println!(
+        "Winning score: {}",
+        winning_score(program, interactive, interactive, record)
+    );
 }
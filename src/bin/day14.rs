@@ -141,7 +141,7 @@ impl NanoFactory {
             }
 
             let reaction = self.reactions.0.get(&chemical).unwrap();
-            let reaction_count = required_quantity.div_ceil(&reaction.output.quantity);
+            let reaction_count = Integer::div_ceil(&required_quantity, &reaction.output.quantity);
 
             requirements.extend(
                 reaction
@@ -1,8 +1,8 @@
 use aoc2019::aoc_input::get_input;
 use aoc2019::intcode::*;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::From;
-use std::ops::{Add, AddAssign, Neg};
+use std::ops::{Add, AddAssign};
 
 #[macro_use]
 extern crate num_derive;
@@ -20,19 +20,6 @@ enum Direction {
     East = 4,
 }
 
-impl Neg for Direction {
-    type Output = Direction;
-
-    fn neg(self) -> Self::Output {
-        match self {
-            Direction::North => Direction::South,
-            Direction::South => Direction::North,
-            Direction::West => Direction::East,
-            Direction::East => Direction::West,
-        }
-    }
-}
-
 #[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive)]
 enum DroidReply {
     HitWall = 0,
@@ -119,55 +106,42 @@ impl RepairDroid {
         }
     }
 
-    fn try_move(&mut self, direction: Direction) -> Option<()> {
-        let dest = self.droid_location + direction.into();
-
+    fn try_move(machine: &mut IntcodeMachine, direction: Direction) -> DroidReply {
         let direction = direction.to_isize().unwrap();
-        self.machine.input.borrow_mut().push_back(direction);
-        self.machine.run().expect("Error running machine");
-        let reply = self.machine.output.borrow_mut().pop_front().unwrap();
-
-        match DroidReply::from_isize(reply).unwrap() {
-            DroidReply::HitWall => {
-                self.map.insert(dest, Tile::Wall);
-                None
-            }
-            DroidReply::MovedStep => {
-                self.map.insert(dest, Tile::Empty);
-                self.droid_location = dest;
-                Some(())
-            }
-            DroidReply::MovedStepFoundOxygenSystem => {
-                self.map.insert(dest, Tile::OxygenSystem);
-                self.droid_location = dest;
-                self.oxygen_system_location = Some(dest);
-                Some(())
-            }
-        }
+        machine.input.borrow_mut().push_back(direction);
+        machine.run().expect("Error running machine");
+        let reply = machine.output.borrow_mut().pop_front().unwrap();
+        DroidReply::from_isize(reply).unwrap()
     }
 
-    fn discover_recurse(&mut self, return_direction: Option<Direction>) {
-        let current_location = self.droid_location;
-
-        for direction in Direction::iter() {
-            let dest = current_location + direction.into();
-            if self.map.get(&dest).is_some() {
-                continue;
-            }
+    /// Explores the whole map breadth-first, forking the droid's program at every step
+    /// instead of physically walking it back.
+    fn discover(&mut self) {
+        let mut queue = VecDeque::new();
+        queue.push_back((self.droid_location, self.machine.fork()));
+
+        while let Some((location, machine)) = queue.pop_front() {
+            for direction in Direction::iter() {
+                let dest = location + direction.into();
+                if self.map.contains_key(&dest) {
+                    continue;
+                }
 
-            if self.try_move(direction).is_some() {
-                self.discover_recurse(Some(-direction));
-                assert_eq!(self.droid_location, current_location);
+                let mut probe = machine.fork();
+                let tile = match RepairDroid::try_move(&mut probe, direction) {
+                    DroidReply::HitWall => Tile::Wall,
+                    DroidReply::MovedStep => Tile::Empty,
+                    DroidReply::MovedStepFoundOxygenSystem => {
+                        self.oxygen_system_location = Some(dest);
+                        Tile::OxygenSystem
+                    }
+                };
+                self.map.insert(dest, tile);
+                if tile != Tile::Wall {
+                    queue.push_back((dest, probe));
+                }
             }
         }
-
-        if let Some(direction) = return_direction {
-            self.try_move(direction).unwrap();
-        }
-    }
-
-    fn discover(&mut self) {
-        self.discover_recurse(None)
     }
 
     fn bfs_layers(
@@ -1,4 +1,5 @@
 use aoc2019::aoc_input::get_input;
+use aoc2019::intcode::ascii::AsciiConsole;
 use aoc2019::intcode::*;
 use std::ops::Index;
 use std::str::FromStr;
@@ -72,14 +73,7 @@ impl FromStr for Map {
 }
 
 fn sum_alignment_parameters(tape: Tape) -> usize {
-    let mut machine = IntcodeMachine::new(tape);
-    machine.run_to_completion().unwrap();
-    let output: String = machine
-        .output
-        .borrow_mut()
-        .drain(..)
-        .map(|n| std::char::from_u32(n as u32).unwrap())
-        .collect();
+    let output = AsciiConsole::new(tape).run().unwrap().text;
 
     print!("{}", output);
 
@@ -3,13 +3,13 @@ use aoc2019::intcode::*;
 
 fn main() {
     let input = get_input(19);
-    let tape = parse_intcode_program(&input);
+    let program = Program::new(parse_intcode_program(&input));
 
     let mut pulled_locations = 0;
 
     for y in 0..50 {
         for x in 0..50 {
-            let mut machine = IntcodeMachine::new(tape.clone());
+            let mut machine = IntcodeMachine::from_program(&program);
             machine.input.borrow_mut().extend(&[x, y]);
             machine.run().unwrap();
             pulled_locations += machine.output.borrow_mut().pop_front().unwrap();
@@ -1,4 +1,5 @@
 use aoc2019::aoc_input::get_input;
+use aoc2019::intcode::symbolic::{Goal, SymbolicSearch};
 use aoc2019::intcode::*;
 
 fn run_program(mut tape: Tape, noun: isize, verb: isize) -> isize {
@@ -8,13 +9,23 @@ fn run_program(mut tape: Tape, noun: isize, verb: isize) -> isize {
     let mut machine = IntcodeMachine::new(tape);
     match machine.run_to_completion() {
         Ok(_) => (),
-        Err(err) => panic!("IntcodeMachine error: {:?}", err),
+        Err(err) => panic!("IntcodeMachine error: {}", err),
     }
 
     machine.read_addr(0).unwrap()
 }
 
 fn find_preimage(original: Tape, preimage: isize) -> Option<(isize, isize)> {
+    let mut search = SymbolicSearch::new(original.clone());
+    search.symbolic_cell(1, 0..=99).symbolic_cell(2, 0..=99);
+    if let Some(solution) = search.solve(Goal::Memory {
+        addr: 0,
+        value: preimage,
+    }) {
+        return Some((solution.cells[0].1, solution.cells[1].1));
+    }
+
+    // The search is best-effort; fall back to trying every pair.
     for noun in 0..100 {
         for verb in 0..100 {
             if run_program(original.clone(), noun, verb) == preimage {
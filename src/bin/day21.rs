@@ -1,5 +1,37 @@
 use aoc2019::aoc_input::get_input;
+use aoc2019::intcode::ascii::AsciiConsole;
+use aoc2019::intcode::*;
+
+/// Jump if there's a hole in the next three tiles and ground to land on four tiles out.
+const WALK_SCRIPT: &[&str] = &[
+    "NOT A J", "NOT B T", "OR T J", "NOT C T", "OR T J", "AND D J", "WALK",
+];
+
+/// As above, but only if after landing the droid can either step forward or jump again.
+const RUN_SCRIPT: &[&str] = &[
+    "NOT A J", "NOT B T", "OR T J", "NOT C T", "OR T J", "AND D J", "NOT E T", "NOT T T", "OR H T",
+    "AND T J", "RUN",
+];
+
+/// Returns the hull damage reported, or the droid's last moments if it fell.
+fn run_springscript(tape: Tape, script: &[&str]) -> Result<isize, String> {
+    let mut console = AsciiConsole::new(tape);
+    console.send_lines(script.iter().copied());
+    let response = console.run().unwrap();
+    match response.values.first() {
+        Some(&damage) => Ok(damage),
+        None => Err(response.text),
+    }
+}
 
 fn main() {
     let input = get_input(21);
+    let tape = parse_intcode_program(&input);
+
+    for (name, script) in &[("Walking", WALK_SCRIPT), ("Running", RUN_SCRIPT)] {
+        match run_springscript(tape.clone(), script) {
+            Ok(damage) => println!("{} hull damage: {}", name, damage),
+            Err(text) => println!("{} droid fell into space:\n{}", name, text),
+        }
+    }
 }
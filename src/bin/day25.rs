@@ -1,5 +1,31 @@
 use aoc2019::aoc_input::get_input;
+use aoc2019::intcode::ascii::AsciiConsole;
+use aoc2019::intcode::replay::Recorder;
+use aoc2019::intcode::*;
 
+const USAGE: &str = "Usage: day25 [--record <file>]";
+
+/// The adventure is played by hand: explore, pick up items and find the weight that gets
+/// past the pressure-sensitive floor.
 fn main() {
     let input = get_input(25);
+    let tape = parse_intcode_program(&input);
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let record = match args.as_slice() {
+        [] => None,
+        [flag, path] if flag == "--record" => Some(path),
+        _ => panic!("{}", USAGE),
+    };
+
+    let mut console = AsciiConsole::new(tape).with_hook(record.map(|_| Recorder::new()));
+    if let Err(e) = console.interact() {
+        println!("Machine fault: {}", e);
+    }
+    if let (Some(path), Some(recorder)) = (record, &console.machine.hook) {
+        recorder
+            .recording
+            .save(path)
+            .expect("Failed writing recording");
+    }
 }
@@ -9,7 +9,7 @@ fn run_program(tape: &Tape, system_id: isize) -> StreamRef {
     );
     match machine.run_to_completion() {
         Ok(_) => (),
-        Err(err) => panic!("IntcodeMachine error: {:?}", err),
+        Err(err) => panic!("IntcodeMachine error: {}", err),
     }
 
     machine.output
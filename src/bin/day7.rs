@@ -1,94 +1,46 @@
 use aoc2019::aoc_input::get_input;
+use aoc2019::intcode::network::{Network, NetworkEvent};
 use aoc2019::intcode::*;
 use itertools::Itertools;
 use std::iter::FromIterator;
 
-struct MachineState {
-    machine: IntcodeMachine,
-    status: StopStatus,
-}
-
-impl MachineState {
-    fn new(machine: IntcodeMachine) -> Self {
-        MachineState {
-            machine,
-            status: StopStatus::BlockedOnInput,
-        }
-    }
-
-    fn resume(&mut self) {
-        self.status = self.machine.run().expect("Error while running machine");
-    }
-}
-
-struct Pipeline {
-    pipeline: Vec<MachineState>,
-}
-
-impl Pipeline {
-    fn new(
-        program: Tape,
-        phase_settings: Vec<&isize>,
-        first_input: StreamRef,
-        last_output: StreamRef,
-    ) -> Self {
-        let mut streams = Vec::<StreamRef>::new();
-        for (i, setting) in phase_settings.iter().enumerate() {
-            let stream = if i == 0 {
-                first_input.clone()
-            } else {
-                new_stream_ref()
-            };
-            stream.borrow_mut().push_back(**setting);
-            streams.push(stream);
-        }
-        streams.push(last_output);
-
-        let mut pipeline = Vec::<MachineState>::new();
-        for w in streams.windows(2) {
-            let machine = IntcodeMachine::new_io(program.clone(), w[0].clone(), w[1].clone());
-            pipeline.push(MachineState::new(machine));
-        }
-
-        Pipeline { pipeline }
+/// Chains one amplifier per phase setting, optionally feeding the last one's output back
+/// into the first, and returns the last signal the final amplifier sent.
+fn calculate_thruster_signal(program: Tape, phase_settings: Vec<&isize>, feedback: bool) -> isize {
+    let mut network = Network::new();
+    let amps: Vec<_> = phase_settings
+        .iter()
+        .enumerate()
+        .map(|(i, setting)| {
+            let amp = network.add_node(format!("amp{}", i), IntcodeMachine::new(program.clone()));
+            network.send(amp, &[**setting]);
+            amp
+        })
+        .collect();
+    for w in amps.windows(2) {
+        network.connect(w[0], w[1]);
     }
-
-    fn completed(&self) -> bool {
-        self.pipeline
-            .iter()
-            .all(|ms| ms.status == StopStatus::Halted)
+    let (first, last) = (amps[0], amps[amps.len() - 1]);
+    if feedback {
+        network.connect(last, first);
     }
 
-    fn run_to_completion(&mut self) {
-        while !self.completed() {
-            for ms in self.pipeline.iter_mut() {
-                ms.resume();
-            }
+    network.send(first, &[0]);
+    match network.run() {
+        NetworkEvent::Halted => network.node(last).last_output().unwrap(),
+        NetworkEvent::Deadlocked(waits) => {
+            panic!("Amplifiers deadlocked: {}", waits.iter().join("; "))
         }
+        event => panic!("Amplifiers stopped early: {:?}", event),
     }
 }
 
 fn calculate_thruster_signal_linear(program: Tape, phase_settings: Vec<&isize>) -> isize {
-    let input = new_stream_ref();
-    let output = new_stream_ref();
-    let mut pipeline = Pipeline::new(program, phase_settings, input.clone(), output.clone());
-
-    input.borrow_mut().push_back(0);
-    pipeline.run_to_completion();
-
-    let signal = output.borrow_mut().pop_back().unwrap();
-    signal
+    calculate_thruster_signal(program, phase_settings, false)
 }
 
 fn calculate_thruster_signal_feedback(program: Tape, phase_settings: Vec<&isize>) -> isize {
-    let inout = new_stream_ref();
-    let mut pipeline = Pipeline::new(program, phase_settings, inout.clone(), inout.clone());
-
-    inout.borrow_mut().push_back(0);
-    pipeline.run_to_completion();
-
-    let signal = inout.borrow_mut().pop_back().unwrap();
-    signal
+    calculate_thruster_signal(program, phase_settings, true)
 }
 
 fn calculate_max_thruster_signal(
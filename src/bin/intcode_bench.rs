@@ -0,0 +1,480 @@
+//! Times the current interpreter against the one it replaced, which ran `digits()` on every
+//! opcode and built a `Vec<Operand>` for every instruction, on day 19 and day 25 style
+//! workloads.
+
+use aoc2019::intcode::asm::assemble;
+use aoc2019::intcode::*;
+use std::time::{Duration, Instant};
+
+/// Stand-in for the day 19 drone program: a short, loop-heavy computation per coordinate.
+const BEAM_SOURCE: &str = "
+        in [x]
+        in [y]
+        add #0, #0, [i]
+loop:   mul [x], #7, [t]
+        mul [y], #5, [u]
+        lt [t], [u], [r]
+        add [i], #1, [i]
+        lt [i], #20, [c]
+        jt [c], #loop
+        out [r]
+        hlt
+x: .data 0
+y: .data 0
+i: .data 0
+t: .data 0
+u: .data 0
+r: .data 0
+c: .data 0
+";
+
+/// Recursive Fibonacci using the usual Intcode calling convention: the caller stores the
+/// return address at [bp+0] and the argument at [bp+1] of the callee's frame, the callee
+/// returns its result in [bp+1].
+const FIB_SOURCE: &str = "
+        arb #stack
+        in [bp+1]
+        add #done, #0, [bp+0]
+        jt #1, #fib
+done:   out [bp+1]
+        hlt
+
+fib:    lt [bp+1], #2, [bp+2]
+        jt [bp+2], #fib_ret
+        add [bp+1], #-1, [bp+4]
+        add #r1, #0, [bp+3]
+        arb #3
+        jt #1, #fib
+r1:     arb #-3
+        add [bp+4], #0, [bp+2]
+        add [bp+1], #-2, [bp+4]
+        add #r2, #0, [bp+3]
+        arb #3
+        jt #1, #fib
+r2:     arb #-3
+        add [bp+2], [bp+4], [bp+1]
+fib_ret: jt #1, [bp+0]
+stack:  .data 0
+";
+
+/// Text printing in the style of the day 25 adventure: walks a length-prefixed message by
+/// patching the address operand of its own `out` instruction, so every character rewrites a
+/// decoded instruction. Prints the message 1000 times.
+const TEXT_SOURCE: &str = "
+        add #1000, #0, [n]
+again:  add [msg], #0, [len]
+        add #msg+1, #0, [char+1]
+char:   out [0]
+        add [char+1], #1, [char+1]
+        add [len], #-1, [len]
+        jt [len], #char
+        add [n], #-1, [n]
+        jt [n], #again
+        hlt
+n:   .data 0
+len: .data 0
+msg: .data 40, 89, 111, 117, 32, 97, 114, 101, 32, 105, 110, 32, 97, 32, 115, 109, 97, 108
+     .data 108, 32, 114, 111, 111, 109, 46, 32, 84, 104, 101, 114, 101, 32, 105, 115, 32, 97
+     .data 32, 100, 111, 111, 114, 46
+";
+
+const ROUNDS: usize = 15;
+
+/// The interpreter as it was before instructions were decoded without allocating and cached,
+/// copied verbatim. Kept only to measure against.
+mod baseline {
+    use aoc2019::digits::digits;
+    use aoc2019::intcode::{new_stream_ref, StreamRef, Tape};
+
+    #[derive(Debug)]
+    pub enum IntcodeError {
+        InvalidOpcodeOperation,
+        NegativeOpcode,
+        InvalidAddressingMode,
+        NegativeAddress,
+        InvalidStoreAddressingMode,
+        DidNotRunToCompletion,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub enum StopStatus {
+        Halted,
+        BlockedOnInput,
+    }
+
+    #[derive(Debug)]
+    enum AddressingMode {
+        AbsoluteAddress,
+        Immediate,
+        BasePointerRelative,
+    }
+
+    #[derive(Debug)]
+    struct Operand {
+        mode: AddressingMode,
+        value: isize,
+    }
+
+    #[derive(Debug)]
+    enum Operation {
+        Add,
+        Multiply,
+        Input,
+        Output,
+        JumpTrue,
+        JumpFalse,
+        LessThan,
+        Equals,
+        AdjustBasePointer,
+        Halt,
+    }
+
+    #[derive(Debug)]
+    struct Opcode {
+        operation: Operation,
+        operands: Vec<Operand>,
+    }
+
+    pub type IntcodeResult<T> = Result<T, IntcodeError>;
+
+    #[derive(Debug)]
+    pub struct Machine {
+        tape: Tape,
+        pub input: StreamRef,
+        pub output: StreamRef,
+        pc: isize,
+        bp: isize,
+    }
+
+    fn parse_addressing_mode(digit: usize) -> IntcodeResult<AddressingMode> {
+        match digit {
+            0 => Ok(AddressingMode::AbsoluteAddress),
+            1 => Ok(AddressingMode::Immediate),
+            2 => Ok(AddressingMode::BasePointerRelative),
+            _ => Err(IntcodeError::InvalidAddressingMode),
+        }
+    }
+
+    impl Machine {
+        pub fn new_io(tape: Tape, input: StreamRef, output: StreamRef) -> Self {
+            Machine {
+                tape,
+                input,
+                output,
+                pc: 0,
+                bp: 0,
+            }
+        }
+
+        pub fn new(tape: Tape) -> Self {
+            Self::new_io(tape, new_stream_ref(), new_stream_ref())
+        }
+
+        fn verify_addr(&mut self, addr: isize) -> IntcodeResult<usize> {
+            if addr < 0 {
+                return Err(IntcodeError::NegativeAddress);
+            }
+
+            let addr = addr as usize;
+            if addr >= self.tape.len() {
+                self.tape.resize(addr + 1, 0);
+            }
+            Ok(addr)
+        }
+
+        pub fn read_addr(&mut self, addr: isize) -> IntcodeResult<isize> {
+            let addr = self.verify_addr(addr)?;
+            Ok(self.tape[addr])
+        }
+
+        fn write_addr(&mut self, addr: isize, value: isize) -> IntcodeResult<()> {
+            let addr = self.verify_addr(addr)?;
+            self.tape[addr] = value;
+            Ok(())
+        }
+
+        fn read_pc(&mut self) -> IntcodeResult<isize> {
+            let value = self.read_addr(self.pc)?;
+            self.pc += 1;
+            Ok(value)
+        }
+
+        fn read_opcode(&mut self) -> IntcodeResult<Opcode> {
+            let opcode = self.read_pc()?;
+            if opcode < 0 {
+                return Err(IntcodeError::NegativeOpcode);
+            }
+
+            let mut digits = digits(opcode as usize, 10);
+            digits.extend(vec![0; 5 - digits.len()]);
+
+            let (operation, operand_count) = match 10 * digits[1] + digits[0] {
+                1 => (Operation::Add, 3),
+                2 => (Operation::Multiply, 3),
+                3 => (Operation::Input, 1),
+                4 => (Operation::Output, 1),
+                5 => (Operation::JumpTrue, 2),
+                6 => (Operation::JumpFalse, 2),
+                7 => (Operation::LessThan, 3),
+                8 => (Operation::Equals, 3),
+                9 => (Operation::AdjustBasePointer, 1),
+                99 => (Operation::Halt, 0),
+                _ => return Err(IntcodeError::InvalidOpcodeOperation),
+            };
+
+            let mut operands = Vec::<Operand>::new();
+            for i in 0..operand_count {
+                let mode = parse_addressing_mode(digits[2 + i])?;
+                let value = self.read_pc()?;
+                operands.push(Operand { mode, value });
+            }
+
+            Ok(Opcode {
+                operation,
+                operands,
+            })
+        }
+
+        fn load(&mut self, op: &Operand) -> IntcodeResult<isize> {
+            match op.mode {
+                AddressingMode::AbsoluteAddress => Ok(self.read_addr(op.value)?),
+                AddressingMode::Immediate => Ok(op.value),
+                AddressingMode::BasePointerRelative => Ok(self.read_addr(self.bp + op.value)?),
+            }
+        }
+
+        fn store(&mut self, op: &Operand, value: isize) -> IntcodeResult<()> {
+            match op.mode {
+                AddressingMode::AbsoluteAddress => Ok(self.write_addr(op.value, value)?),
+                AddressingMode::BasePointerRelative => {
+                    Ok(self.write_addr(self.bp + op.value, value)?)
+                }
+                AddressingMode::Immediate => Err(IntcodeError::InvalidStoreAddressingMode),
+            }
+        }
+
+        fn jump_conditional(&mut self, condition: bool, target: isize) -> IntcodeResult<()> {
+            if condition {
+                self.verify_addr(target)?;
+                self.pc = target;
+            }
+            Ok(())
+        }
+
+        fn tick(&mut self) -> IntcodeResult<Option<StopStatus>> {
+            let start_pc = self.pc;
+            let opcode = self.read_opcode()?;
+
+            match opcode.operation {
+                Operation::Add => {
+                    let value = self.load(&opcode.operands[0])? + self.load(&opcode.operands[1])?;
+                    self.store(&opcode.operands[2], value)?;
+                }
+                Operation::Multiply => {
+                    let value = self.load(&opcode.operands[0])? * self.load(&opcode.operands[1])?;
+                    self.store(&opcode.operands[2], value)?;
+                }
+                Operation::Input => {
+                    let input = self.input.borrow_mut().pop_front();
+                    match input {
+                        Some(value) => self.store(&opcode.operands[0], value)?,
+                        None => {
+                            self.pc = start_pc;
+                            return Ok(Some(StopStatus::BlockedOnInput));
+                        }
+                    };
+                }
+                Operation::Output => {
+                    let value = self.load(&opcode.operands[0])?;
+                    self.output.borrow_mut().push_back(value);
+                }
+                Operation::JumpTrue => {
+                    let condition = self.load(&opcode.operands[0])?;
+                    let target = self.load(&opcode.operands[1])?;
+                    self.jump_conditional(condition != 0, target)?;
+                }
+                Operation::JumpFalse => {
+                    let condition = self.load(&opcode.operands[0])?;
+                    let target = self.load(&opcode.operands[1])?;
+                    self.jump_conditional(condition == 0, target)?;
+                }
+                Operation::LessThan => {
+                    let value = self.load(&opcode.operands[0])? < self.load(&opcode.operands[1])?;
+                    self.store(&opcode.operands[2], value as isize)?;
+                }
+                Operation::Equals => {
+                    let value =
+                        self.load(&opcode.operands[0])? == self.load(&opcode.operands[1])?;
+                    self.store(&opcode.operands[2], value as isize)?;
+                }
+                Operation::AdjustBasePointer => {
+                    let newbp = self.bp + self.load(&opcode.operands[0])?;
+                    self.verify_addr(newbp)?;
+                    self.bp = newbp;
+                }
+                Operation::Halt => {
+                    self.pc = start_pc;
+                    return Ok(Some(StopStatus::Halted));
+                }
+            };
+
+            Ok(None)
+        }
+
+        pub fn run(&mut self) -> IntcodeResult<StopStatus> {
+            loop {
+                match self.tick() {
+                    Ok(None) => continue,
+                    Ok(Some(status)) => return Ok(status),
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        pub fn run_to_completion(&mut self) -> IntcodeResult<()> {
+            match self.run()? {
+                StopStatus::Halted => Ok(()),
+                _ => Err(IntcodeError::DidNotRunToCompletion),
+            }
+        }
+    }
+}
+
+/// A workload returns its result, so the two interpreters can be checked against each other,
+/// and the current interpreter's instruction count.
+struct Workload {
+    name: &'static str,
+    tape: Tape,
+    baseline: fn(&Tape) -> isize,
+    current: fn(&Tape) -> (isize, usize),
+}
+
+fn beam_scan_baseline(tape: &Tape) -> isize {
+    let mut pulled = 0;
+    for y in 0..50 {
+        for x in 0..50 {
+            let mut machine = baseline::Machine::new(tape.clone());
+            machine.input.borrow_mut().extend(&[x, y]);
+            machine.run_to_completion().unwrap();
+            pulled += machine.output.borrow_mut().pop_front().unwrap();
+        }
+    }
+    pulled
+}
+
+fn beam_scan(tape: &Tape) -> (isize, usize) {
+    let program = Program::new(tape.clone());
+    let mut pulled = 0;
+    let mut instructions = 0;
+    for y in 0..50 {
+        for x in 0..50 {
+            let mut machine = IntcodeMachine::from_program(&program);
+            machine.input.borrow_mut().extend(&[x, y]);
+            machine.run_to_completion().unwrap();
+            pulled += machine.output.borrow_mut().pop_front().unwrap();
+            instructions += machine.instruction_count();
+        }
+    }
+    (pulled, instructions)
+}
+
+fn fib_baseline(tape: &Tape) -> isize {
+    let mut machine = baseline::Machine::new(tape.clone());
+    machine.input.borrow_mut().push_back(24);
+    machine.run_to_completion().unwrap();
+    let result = machine.output.borrow_mut().pop_front().unwrap();
+    result
+}
+
+fn fib(tape: &Tape) -> (isize, usize) {
+    let mut machine =
+        IntcodeMachine::new_io(tape.clone(), new_stream_ref_from(24), new_stream_ref());
+    machine.run_to_completion().unwrap();
+    let result = machine.output.borrow_mut().pop_front().unwrap();
+    (result, machine.instruction_count())
+}
+
+fn text_baseline(tape: &Tape) -> isize {
+    let mut machine = baseline::Machine::new(tape.clone());
+    machine.run_to_completion().unwrap();
+    let checksum = machine.output.borrow().iter().sum();
+    checksum
+}
+
+fn text(tape: &Tape) -> (isize, usize) {
+    let mut machine = IntcodeMachine::new_io(tape.clone(), Stream::new(), Vec::new());
+    machine.run_to_completion().unwrap();
+    (machine.output.iter().sum(), machine.instruction_count())
+}
+
+fn time<T>(run: impl FnOnce() -> T) -> Duration {
+    let start = Instant::now();
+    run();
+    start.elapsed()
+}
+
+fn bench(workload: &Workload) {
+    let tape = &workload.tape;
+    let expected = (workload.baseline)(tape);
+    let (result, instructions) = (workload.current)(tape);
+    assert_eq!(result, expected, "{}: interpreters disagree", workload.name);
+
+    // Alternate between the two, so that a slow patch on the machine hits both alike, and
+    // report medians.
+    let mut before = Vec::new();
+    let mut after = Vec::new();
+    let mut speedups = Vec::new();
+    for _ in 0..ROUNDS {
+        let old = time(|| (workload.baseline)(tape));
+        let new = time(|| (workload.current)(tape));
+        before.push(old);
+        after.push(new);
+        speedups.push(old.as_secs_f64() / new.as_secs_f64());
+    }
+    let (before, after) = (median(&mut before), median(&mut after));
+    let speedup = median(&mut speedups);
+
+    let mips = |elapsed: Duration| instructions as f64 / elapsed.as_secs_f64() / 1e6;
+    println!(
+        "{:<10} result {:>8}  {:>9} instructions  before {:>9.2?} ({:>5.1} M/s)  after {:>9.2?} ({:>5.1} M/s)  {:.1}x",
+        workload.name,
+        result,
+        instructions,
+        before,
+        mips(before),
+        after,
+        mips(after),
+        speedup
+    );
+}
+
+fn median<T: PartialOrd + Copy>(values: &mut [T]) -> T {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    values[values.len() / 2]
+}
+
+fn main() {
+    let workloads = [
+        Workload {
+            name: "beam scan",
+            tape: assemble(BEAM_SOURCE).unwrap(),
+            baseline: beam_scan_baseline,
+            current: beam_scan,
+        },
+        Workload {
+            name: "fib(24)",
+            tape: assemble(FIB_SOURCE).unwrap(),
+            baseline: fib_baseline,
+            current: fib,
+        },
+        Workload {
+            name: "text",
+            tape: assemble(TEXT_SOURCE).unwrap(),
+            baseline: text_baseline,
+            current: text,
+        },
+    ];
+    for workload in &workloads {
+        bench(workload);
+    }
+}
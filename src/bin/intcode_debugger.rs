@@ -0,0 +1,214 @@
+use aoc2019::intcode::debugger::{DebugEvent, Debugger};
+use aoc2019::intcode::format::load_day_or_file;
+use aoc2019::intcode::*;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+s, step [n]          execute n instructions (default 1)
+c, continue          run until a breakpoint, watchpoint or stop
+rs, rstep [n]        step back n instructions (default 1)
+rc, rcontinue        run backwards to a breakpoint or watchpoint
+lw, lastwrite <addr> run backwards to the last write of addr
+history [n]          show, or set to n, how many instructions can be stepped back
+b, break <pc>        set a breakpoint
+d, delete <pc>       remove a breakpoint
+w, watch <addr>      stop when the value at addr changes
+u, unwatch <addr>    remove a watchpoint
+i, info              list breakpoints and watchpoints
+r, regs              show pc and bp
+x <addr> [n]         examine n words of memory
+set <addr> <value>   modify memory
+dis [addr] [n]       disassemble n instructions (default: at pc)
+in <v>...            push values onto the input stream
+ascii <text>         push text and a newline onto the input stream
+out                  drain and print the output stream
+q, quit              exit";
+
+fn parse_args(args: &[&str]) -> Option<Vec<isize>> {
+    args.iter().map(|a| a.parse().ok()).collect()
+}
+
+fn print_location(dbg: &Debugger) {
+    let pc = dbg.machine.pc();
+    match dbg.current_opcode() {
+        Ok(opcode) => println!("{:>6}  {}", pc, opcode),
+        Err(e) => println!("{:>6}  <{}>", pc, e),
+    }
+}
+
+fn print_event(dbg: &Debugger, event: DebugEvent) {
+    match event {
+        DebugEvent::Stepped => (),
+        DebugEvent::Breakpoint(pc) => println!("Breakpoint at {}", pc),
+        DebugEvent::Watchpoint { addr, old, new } => {
+            println!("Watchpoint [{}]: {} -> {}", addr, old, new)
+        }
+        DebugEvent::Stopped(status) => println!("Machine stopped: {:?}", status),
+        DebugEvent::Fault(e) => println!("Machine fault: {}", e),
+        DebugEvent::LastWrite { pc, addr, old, new } => {
+            println!("[{}] written by {}: {} -> {}", addr, pc, old, new)
+        }
+        DebugEvent::HistoryStart => println!("Reached the start of the recorded history"),
+    }
+    print_location(dbg);
+}
+
+fn disassemble_from(dbg: &Debugger, addr: isize, count: usize) {
+    let mut addr = addr.max(0) as usize;
+    for _ in 0..count {
+        match dbg.machine.decode_at(addr) {
+            Ok(opcode) => {
+                let marker = if addr as isize == dbg.machine.pc() {
+                    "=>"
+                } else {
+                    "  "
+                };
+                println!("{} {:>6}  {}", marker, addr, opcode);
+                addr += opcode.size();
+            }
+            Err(_) => {
+                println!("   {:>6}  .data {}", addr, dbg.peek(addr as isize).unwrap());
+                addr += 1;
+            }
+        }
+    }
+}
+
+fn execute(dbg: &mut Debugger, command: &str, args: &[&str]) -> Result<(), String> {
+    let numbers = parse_args(args).ok_or("Arguments must be integers");
+    match (command, args.len()) {
+        ("s", _) | ("step", _) => {
+            let count = numbers?.first().copied().unwrap_or(1).max(1) as usize;
+            let event = dbg.step(count);
+            print_event(dbg, event);
+        }
+        ("c", 0) | ("continue", 0) => {
+            let event = dbg.cont();
+            print_event(dbg, event);
+        }
+        ("rs", _) | ("rstep", _) => {
+            let count = numbers?.first().copied().unwrap_or(1).max(1) as usize;
+            let event = dbg.step_back(count);
+            print_event(dbg, event);
+        }
+        ("rc", 0) | ("rcontinue", 0) => {
+            let event = dbg.reverse_cont();
+            print_event(dbg, event);
+        }
+        ("lw", 1) | ("lastwrite", 1) => {
+            let addr = numbers?[0];
+            if addr < 0 {
+                return Err(format!("Invalid address {}", addr));
+            }
+            let event = dbg.back_to_write(addr as usize);
+            print_event(dbg, event);
+        }
+        ("history", 0) => {
+            println!("{} instructions recorded", dbg.history_len());
+        }
+        ("history", 1) => {
+            let limit = numbers?[0];
+            if limit < 0 {
+                return Err(format!("Invalid history limit {}", limit));
+            }
+            dbg.set_history_limit(limit as usize);
+        }
+        ("b", 1) | ("break", 1) => {
+            dbg.add_breakpoint(numbers?[0]);
+        }
+        ("d", 1) | ("delete", 1) => {
+            if !dbg.remove_breakpoint(numbers?[0]) {
+                return Err("No such breakpoint".to_string());
+            }
+        }
+        ("w", 1) | ("watch", 1) => {
+            if !dbg.add_watchpoint(numbers?[0]) {
+                return Err("Invalid or duplicate watchpoint".to_string());
+            }
+        }
+        ("u", 1) | ("unwatch", 1) => {
+            if !dbg.remove_watchpoint(numbers?[0]) {
+                return Err("No such watchpoint".to_string());
+            }
+        }
+        ("i", 0) | ("info", 0) => {
+            println!("Breakpoints: {:?}", dbg.breakpoints().collect::<Vec<_>>());
+            println!("Watchpoints: {:?}", dbg.watchpoints().collect::<Vec<_>>());
+        }
+        ("r", 0) | ("regs", 0) => {
+            println!("pc = {}, bp = {}", dbg.machine.pc(), dbg.machine.bp());
+        }
+        ("x", 1) | ("x", 2) => {
+            let numbers = numbers?;
+            let count = numbers.get(1).copied().unwrap_or(1);
+            let end = numbers[0]
+                .checked_add(count)
+                .ok_or("Usage: x <addr> [n], with addr + n within range")?;
+            for addr in numbers[0]..end {
+                match dbg.peek(addr) {
+                    Some(value) => println!("[{}] = {}", addr, value),
+                    None => return Err(format!("Invalid address {}", addr)),
+                }
+            }
+        }
+        ("set", 2) => {
+            let numbers = numbers?;
+            dbg.poke(numbers[0], numbers[1])
+                .map_err(|e| e.to_string())?;
+        }
+        ("dis", _) => {
+            let numbers = numbers?;
+            let addr = numbers.first().copied().unwrap_or_else(|| dbg.machine.pc());
+            let count = numbers.get(1).copied().unwrap_or(10).max(0) as usize;
+            disassemble_from(dbg, addr, count);
+        }
+        ("in", _) => {
+            dbg.machine.input.borrow_mut().extend(numbers?);
+        }
+        ("ascii", _) => {
+            let text = args.join(" ") + "\n";
+            let values = text.bytes().map(|b| b as isize);
+            dbg.machine.input.borrow_mut().extend(values);
+        }
+        ("out", 0) => {
+            let output: Vec<_> = dbg.machine.output.borrow_mut().drain(..).collect();
+            println!("{:?}", output);
+        }
+        ("h", _) | ("help", _) => println!("{}", HELP),
+        _ => return Err(format!("Unknown command '{}', try 'help'", command)),
+    }
+    Ok(())
+}
+
+fn main() {
+    let arg = std::env::args()
+        .nth(1)
+        .expect("Usage: intcode_debugger <day | program file>");
+    let tape = load_day_or_file(&arg);
+
+    let mut dbg = Debugger::new(IntcodeMachine::new(tape));
+    print_location(&dbg);
+
+    let stdin = io::stdin();
+    loop {
+        print!("(icdb) ");
+        io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => continue,
+        };
+        if command == "q" || command == "quit" {
+            break;
+        }
+        if let Err(message) = execute(&mut dbg, command, args) {
+            println!("{}", message);
+        }
+    }
+}
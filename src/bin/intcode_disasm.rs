@@ -0,0 +1,25 @@
+use aoc2019::intcode::cfg::build_cfg;
+use aoc2019::intcode::decompile::decompile;
+use aoc2019::intcode::disasm::disassemble;
+use aoc2019::intcode::format::load_day_or_file;
+
+const USAGE: &str = "Usage: intcode_disasm [--cfg | --dot | --decompile] <day | program file>";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (mode, arg) = match args.as_slice() {
+        [arg] => ("", arg),
+        [mode, arg] if ["--cfg", "--dot", "--decompile"].contains(&mode.as_str()) => {
+            (mode.as_str(), arg)
+        }
+        _ => panic!("{}", USAGE),
+    };
+    let tape = load_day_or_file(arg);
+
+    match mode {
+        "--cfg" => print!("{}", build_cfg(&tape)),
+        "--dot" => print!("{}", build_cfg(&tape).to_dot()),
+        "--decompile" => print!("{}", decompile(&tape)),
+        _ => print!("{}", disassemble(&tape)),
+    }
+}
@@ -0,0 +1,35 @@
+use aoc2019::intcode::disasm::disassemble;
+use aoc2019::intcode::format::load_day_or_file;
+use aoc2019::intcode::profile::Profiler;
+use aoc2019::intcode::*;
+
+const USAGE: &str = "Usage: intcode_profile <day | program file> [input]...";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (arg, inputs) = args.split_first().expect(USAGE);
+    let tape = load_day_or_file(arg);
+    let inputs: Vec<isize> = inputs.iter().map(|v| v.parse().expect(USAGE)).collect();
+
+    let mut machine = IntcodeMachine::new_io(
+        tape.clone(),
+        new_stream_ref_from_iter(inputs),
+        new_stream_ref(),
+    )
+    .with_hook(Profiler::new());
+    match machine.run() {
+        Ok(status) => println!("Machine stopped: {:?}", status),
+        Err(e) => println!("Machine fault: {}", e),
+    }
+    let outputs: Vec<String> = machine
+        .output
+        .borrow()
+        .iter()
+        .map(|v| v.to_string())
+        .collect();
+    println!("Output: {}\n", outputs.join(","));
+
+    print!("{}", machine.hook.report(20));
+    println!();
+    print!("{}", machine.hook.annotate(&disassemble(&tape)));
+}
@@ -1,39 +1,197 @@
-use crate::digits::digits;
 use std::cell::RefCell;
 use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::Deref;
 use std::rc::Rc;
 
-#[derive(Debug)]
+use extensions::{ExtensionAction, ExtensionCall, ExtensionRegistry};
+pub use format::{try_parse_intcode_program, ParseError, ParseErrorKind};
+use memory::{LimitExceeded, Memory};
+use std::sync::Arc;
+
+pub mod ascii;
+pub mod asm;
+pub mod cfg;
+pub mod channel;
+pub mod debugger;
+pub mod decompile;
+pub mod disasm;
+pub mod extensions;
+pub mod format;
+pub mod memory;
+pub mod network;
+pub mod ports;
+pub mod profile;
+pub mod replay;
+pub mod symbolic;
+pub mod trace;
+pub mod wide;
+
+/// Execution faults. `pc` is the address of the faulting instruction, `opcode` its raw opcode
+/// word and `operand` the zero-based index of the operand being evaluated.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum IntcodeError {
-    InvalidOpcodeOperation,
-    NegativeOpcode,
-    InvalidAddressingMode,
-    NegativeAddress,
-    InvalidStoreAddressingMode,
-    DidNotRunToCompletion,
+    InvalidOpcodeOperation {
+        pc: isize,
+        opcode: isize,
+    },
+    NegativeOpcode {
+        pc: isize,
+        opcode: isize,
+    },
+    InvalidAddressingMode {
+        pc: isize,
+        opcode: isize,
+        operand: usize,
+        mode: usize,
+    },
+    /// `opcode` and `operand` are absent when the access came from outside an instruction,
+    /// e.g. `IntcodeMachine::read_addr`.
+    NegativeAddress {
+        pc: isize,
+        opcode: Option<isize>,
+        operand: Option<usize>,
+        addr: isize,
+    },
+    InvalidStoreAddressingMode {
+        pc: isize,
+        opcode: isize,
+        operand: usize,
+    },
+    DidNotRunToCompletion {
+        pc: isize,
+        status: StopStatus,
+    },
+    /// An `ADD`, `MUL` or base-pointer calculation overflowed under `Arithmetic::Checked`,
+    /// an instruction runs past the highest address, or a `wide::WideMachine` address
+    /// doesn't fit in an `isize`.
+    Overflow {
+        pc: isize,
+        opcode: isize,
+    },
+    /// An extension's handler reported an error, or asked for something its opcode can't do.
+    ExtensionFailed {
+        pc: isize,
+        opcode: isize,
+        message: String,
+    },
+    /// Writing `addr` would take the machine past its memory limit of `limit` words.
+    MemoryLimitExceeded {
+        pc: isize,
+        addr: usize,
+        limit: usize,
+    },
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum StopStatus {
     Halted,
     BlockedOnInput,
+    BudgetExhausted,
+    /// Only produced when opted into with `IntcodeMachine::yield_on_output`.
+    OutputReady,
 }
 
-#[derive(Debug)]
-enum AddressingMode {
+/// How additions and multiplications, including base-pointer adjustments and relative
+/// addresses, treat results that don't fit in a word. `Checked` is the default, so a program
+/// that used to wrap silently in a release build now faults instead; `Wrapping` restores the
+/// old release behaviour, and `wide::WideMachine` runs programs that need larger values.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Arithmetic {
+    /// Fail with `IntcodeError::Overflow`.
+    #[default]
+    Checked,
+    /// Two's complement wrap-around.
+    Wrapping,
+}
+
+/// A machine word. Programs normally run on `isize` words; `wide::WideMachine` runs them on
+/// `i128` ones. Whatever the word, addresses and the base pointer must fit in an `isize`.
+pub trait Word: Copy + Default + Ord + fmt::Debug + fmt::Display {
+    /// The type of `unsigned_abs`, which can hold the magnitude of every word.
+    type Unsigned: fmt::Display;
+
+    fn from_isize(value: isize) -> Self;
+    /// `None` if the value doesn't fit in an `isize`.
+    fn to_isize(self) -> Option<isize>;
+    fn checked_add(self, other: Self) -> Option<Self>;
+    fn checked_mul(self, other: Self) -> Option<Self>;
+    fn wrapping_add(self, other: Self) -> Self;
+    fn wrapping_mul(self, other: Self) -> Self;
+    fn unsigned_abs(self) -> Self::Unsigned;
+}
+
+macro_rules! impl_word {
+    ($word:ty, $unsigned:ty) => {
+        impl Word for $word {
+            type Unsigned = $unsigned;
+
+            #[inline]
+            fn from_isize(value: isize) -> Self {
+                value as $word
+            }
+
+            #[inline]
+            fn to_isize(self) -> Option<isize> {
+                isize::try_from(self).ok()
+            }
+
+            #[inline]
+            fn checked_add(self, other: Self) -> Option<Self> {
+                <$word>::checked_add(self, other)
+            }
+
+            #[inline]
+            fn checked_mul(self, other: Self) -> Option<Self> {
+                <$word>::checked_mul(self, other)
+            }
+
+            #[inline]
+            fn wrapping_add(self, other: Self) -> Self {
+                <$word>::wrapping_add(self, other)
+            }
+
+            #[inline]
+            fn wrapping_mul(self, other: Self) -> Self {
+                <$word>::wrapping_mul(self, other)
+            }
+
+            fn unsigned_abs(self) -> $unsigned {
+                <$word>::unsigned_abs(self)
+            }
+        }
+    };
+}
+
+impl_word!(isize, usize);
+impl_word!(i128, u128);
+
+/// Errors carry `isize` values, so wider words are reported clamped to the nearest `isize`.
+fn saturate<W: Word>(value: W) -> isize {
+    value.to_isize().unwrap_or(if value < W::default() {
+        isize::MIN
+    } else {
+        isize::MAX
+    })
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AddressingMode {
     AbsoluteAddress,
     Immediate,
     BasePointerRelative,
 }
 
-#[derive(Debug)]
-struct Operand {
-    mode: AddressingMode,
-    value: isize,
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Operand<W = isize> {
+    pub mode: AddressingMode,
+    pub value: W,
 }
 
-#[derive(Debug)]
-enum Operation {
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Operation {
     Add,
     Multiply,
     Input,
@@ -44,12 +202,32 @@ enum Operation {
     Equals,
     AdjustBasePointer,
     Halt,
+    Extended(ExtendedOperation),
 }
 
-#[derive(Debug)]
-struct Opcode {
-    operation: Operation,
-    operands: Vec<Operand>,
+/// An operation registered through an `extensions::ExtensionRegistry`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ExtendedOperation {
+    pub code: u8,
+    pub mnemonic: &'static str,
+    pub operand_count: u8,
+    pub stores_result: bool,
+}
+
+/// No instruction takes more operands than this.
+pub const MAX_OPERANDS: usize = 3;
+
+/// An instruction's operands, stored inline so decoding never allocates. Derefs to a slice.
+#[derive(Copy, Clone)]
+pub struct Operands<W = isize> {
+    items: [Operand<W>; MAX_OPERANDS],
+    len: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Opcode<W = isize> {
+    pub operation: Operation,
+    pub operands: Operands<W>,
 }
 
 pub type IntcodeResult<T> = Result<T, IntcodeError>;
@@ -57,187 +235,1082 @@ pub type Tape = Vec<isize>;
 pub type Stream = VecDeque<isize>;
 pub type StreamRef = Rc<RefCell<Stream>>;
 
+/// Observes execution of an `IntcodeMachine`. Every method defaults to a no-op, so the default
+/// `NoHook` compiles away entirely. `on_instruction` fires after decoding and before any of
+/// the instruction's effects; an input instruction that blocks is not reported.
+pub trait IntcodeHook<W = isize> {
+    fn on_instruction(&mut self, _pc: isize, _opcode: &Opcode<W>) {}
+    fn on_read(&mut self, _addr: usize, _value: W) {}
+    fn on_write(&mut self, _addr: usize, _old: W, _new: W) {}
+    fn on_input(&mut self, _value: W) {}
+    fn on_output(&mut self, _value: W) {}
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct NoHook;
+
+impl<W> IntcodeHook<W> for NoHook {}
+
+impl<W: Copy, A: IntcodeHook<W>, B: IntcodeHook<W>> IntcodeHook<W> for (A, B) {
+    fn on_instruction(&mut self, pc: isize, opcode: &Opcode<W>) {
+        self.0.on_instruction(pc, opcode);
+        self.1.on_instruction(pc, opcode);
+    }
+
+    fn on_read(&mut self, addr: usize, value: W) {
+        self.0.on_read(addr, value);
+        self.1.on_read(addr, value);
+    }
+
+    fn on_write(&mut self, addr: usize, old: W, new: W) {
+        self.0.on_write(addr, old, new);
+        self.1.on_write(addr, old, new);
+    }
+
+    fn on_input(&mut self, value: W) {
+        self.0.on_input(value);
+        self.1.on_input(value);
+    }
+
+    fn on_output(&mut self, value: W) {
+        self.0.on_output(value);
+        self.1.on_output(value);
+    }
+}
+
+/// An optional hook, so one can be attached only when asked for without changing the
+/// machine's type.
+impl<W, H: IntcodeHook<W>> IntcodeHook<W> for Option<H> {
+    fn on_instruction(&mut self, pc: isize, opcode: &Opcode<W>) {
+        if let Some(hook) = self {
+            hook.on_instruction(pc, opcode);
+        }
+    }
+
+    fn on_read(&mut self, addr: usize, value: W) {
+        if let Some(hook) = self {
+            hook.on_read(addr, value);
+        }
+    }
+
+    fn on_write(&mut self, addr: usize, old: W, new: W) {
+        if let Some(hook) = self {
+            hook.on_write(addr, old, new);
+        }
+    }
+
+    fn on_input(&mut self, value: W) {
+        if let Some(hook) = self {
+            hook.on_input(value);
+        }
+    }
+
+    fn on_output(&mut self, value: W) {
+        if let Some(hook) = self {
+            hook.on_output(value);
+        }
+    }
+}
+
+/// Where an `IntcodeMachine` takes its input from. Returning `None` makes the machine stop
+/// with `StopStatus::BlockedOnInput`; it will retry the same instruction when resumed.
+pub trait IntcodeInput<W = isize> {
+    fn read(&mut self) -> Option<W>;
+}
+
+pub trait IntcodeOutput<W = isize> {
+    fn write(&mut self, value: W);
+}
+
+impl<W> IntcodeInput<W> for Rc<RefCell<VecDeque<W>>> {
+    fn read(&mut self) -> Option<W> {
+        self.borrow_mut().pop_front()
+    }
+}
+
+impl<W> IntcodeOutput<W> for Rc<RefCell<VecDeque<W>>> {
+    fn write(&mut self, value: W) {
+        self.borrow_mut().push_back(value);
+    }
+}
+
+/// Runs programs on `W` words; `wide::WideMachine` is the `i128` version.
 #[derive(Debug)]
-pub struct IntcodeMachine {
-    tape: Tape,
-    pub input: StreamRef,
-    pub output: StreamRef,
+pub struct IntcodeMachine<I = StreamRef, O = StreamRef, H = NoHook, W = isize> {
+    memory: Memory<W>,
+    pub input: I,
+    pub output: O,
     pc: isize,
     bp: isize,
+    instructions: usize,
+    output_batch: Option<usize>,
+    unyielded_outputs: usize,
+    decode_cache: Option<DecodeCache<W>>,
+    /// Addresses below this are decode-cached: the length of the tape the machine was built
+    /// from, so the cache can't grow with memory the program allocates.
+    code_len: usize,
+    arithmetic: Arithmetic,
+    extensions: Option<Arc<ExtensionRegistry<W>>>,
+    pub hook: H,
+}
+
+/// Copy of everything that determines a machine's future behaviour: memory, registers and
+/// the contents of both streams, plus the instruction counter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineSnapshot<W: Word = isize> {
+    pub memory: Memory<W>,
+    pub pc: isize,
+    pub bp: isize,
+    pub input: VecDeque<W>,
+    pub output: VecDeque<W>,
+    pub instructions: usize,
 }
 
-fn parse_addressing_mode(digit: usize) -> IntcodeResult<AddressingMode> {
+/// A decoded instruction along with where it came from, kept around for error reporting and
+/// in the decode cache.
+#[derive(Debug, Copy, Clone)]
+struct Instruction<W> {
+    pc: isize,
+    /// The opcode word, saturated to an `isize` for error reporting.
+    word: isize,
+    opcode: Opcode<W>,
+}
+
+/// Decoded instructions by address. Machines share one until a write touches a decoded
+/// instruction, at which point the writer gets its own copy.
+type DecodeCache<W> = Arc<Vec<Option<Instruction<W>>>>;
+
+/// A tape with every instruction decoded ahead of time, for running the same program in many
+/// machines, like day 19's drone program once per coordinate. Machines built from it with
+/// `IntcodeMachine::from_program` start with a full decode cache instead of an empty one.
+#[derive(Debug, Clone)]
+pub struct Program<W = isize> {
+    tape: Vec<W>,
+    decoded: DecodeCache<W>,
+}
+
+fn parse_addressing_mode(digit: usize) -> Option<AddressingMode> {
     match digit {
-        0 => Ok(AddressingMode::AbsoluteAddress),
-        1 => Ok(AddressingMode::Immediate),
-        2 => Ok(AddressingMode::BasePointerRelative),
-        _ => Err(IntcodeError::InvalidAddressingMode),
+        0 => Some(AddressingMode::AbsoluteAddress),
+        1 => Some(AddressingMode::Immediate),
+        2 => Some(AddressingMode::BasePointerRelative),
+        _ => None,
     }
 }
 
-impl IntcodeMachine {
-    pub fn new_io(tape: Tape, input: StreamRef, output: StreamRef) -> Self {
-        IntcodeMachine {
+impl fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntcodeError::InvalidOpcodeOperation { pc, opcode } => write!(
+                f,
+                "invalid operation {} in opcode {} at pc {}",
+                opcode % 100,
+                opcode,
+                pc
+            ),
+            IntcodeError::NegativeOpcode { pc, opcode } => {
+                write!(f, "negative opcode {} at pc {}", opcode, pc)
+            }
+            IntcodeError::InvalidAddressingMode {
+                pc,
+                opcode,
+                operand,
+                mode,
+            } => write!(
+                f,
+                "invalid addressing mode {} for operand {} of opcode {} at pc {}",
+                mode, operand, opcode, pc
+            ),
+            IntcodeError::NegativeAddress {
+                pc,
+                opcode: Some(opcode),
+                operand: Some(operand),
+                addr,
+            } => write!(
+                f,
+                "negative address {} in operand {} of opcode {} at pc {}",
+                addr, operand, opcode, pc
+            ),
+            IntcodeError::NegativeAddress { pc, addr, .. } => {
+                write!(f, "negative address {} accessed at pc {}", addr, pc)
+            }
+            IntcodeError::InvalidStoreAddressingMode {
+                pc,
+                opcode,
+                operand,
+            } => write!(
+                f,
+                "immediate-mode store to operand {} of opcode {} at pc {}",
+                operand, opcode, pc
+            ),
+            IntcodeError::DidNotRunToCompletion { pc, status } => {
+                write!(f, "did not run to completion: {:?} at pc {}", status, pc)
+            }
+            IntcodeError::Overflow { pc, opcode } => {
+                write!(f, "arithmetic overflow in opcode {} at pc {}", opcode, pc)
+            }
+            IntcodeError::ExtensionFailed {
+                pc,
+                opcode,
+                message,
+            } => write!(f, "opcode {} at pc {} failed: {}", opcode, pc, message),
+            IntcodeError::MemoryLimitExceeded { pc, addr, limit } => write!(
+                f,
+                "write to address {} at pc {} exceeds memory limit of {} words",
+                addr, pc, limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for IntcodeError {}
+
+impl AddressingMode {
+    pub fn digit(self) -> usize {
+        match self {
+            AddressingMode::AbsoluteAddress => 0,
+            AddressingMode::Immediate => 1,
+            AddressingMode::BasePointerRelative => 2,
+        }
+    }
+}
+
+pub const OPERATIONS: [Operation; 10] = [
+    Operation::Add,
+    Operation::Multiply,
+    Operation::Input,
+    Operation::Output,
+    Operation::JumpTrue,
+    Operation::JumpFalse,
+    Operation::LessThan,
+    Operation::Equals,
+    Operation::AdjustBasePointer,
+    Operation::Halt,
+];
+
+impl Operation {
+    /// Looks up a built-in operation.
+    pub fn from_code(code: usize) -> Option<Operation> {
+        OPERATIONS.iter().copied().find(|op| op.code() == code)
+    }
+
+    pub fn code(self) -> usize {
+        match self {
+            Operation::Add => 1,
+            Operation::Multiply => 2,
+            Operation::Input => 3,
+            Operation::Output => 4,
+            Operation::JumpTrue => 5,
+            Operation::JumpFalse => 6,
+            Operation::LessThan => 7,
+            Operation::Equals => 8,
+            Operation::AdjustBasePointer => 9,
+            Operation::Halt => 99,
+            Operation::Extended(op) => op.code as usize,
+        }
+    }
+
+    /// Whether the last operand is written to rather than read from.
+    pub fn stores_result(self) -> bool {
+        match self {
+            Operation::Add
+            | Operation::Multiply
+            | Operation::Input
+            | Operation::LessThan
+            | Operation::Equals => true,
+            Operation::Extended(op) => op.stores_result,
+            _ => false,
+        }
+    }
+
+    pub fn operand_count(self) -> usize {
+        match self {
+            Operation::Add | Operation::Multiply => 3,
+            Operation::Input | Operation::Output => 1,
+            Operation::JumpTrue | Operation::JumpFalse => 2,
+            Operation::LessThan | Operation::Equals => 3,
+            Operation::AdjustBasePointer => 1,
+            Operation::Halt => 0,
+            Operation::Extended(op) => op.operand_count as usize,
+        }
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Operation::Add => "ADD",
+            Operation::Multiply => "MUL",
+            Operation::Input => "IN",
+            Operation::Output => "OUT",
+            Operation::JumpTrue => "JT",
+            Operation::JumpFalse => "JF",
+            Operation::LessThan => "LT",
+            Operation::Equals => "EQ",
+            Operation::AdjustBasePointer => "ARB",
+            Operation::Halt => "HLT",
+            Operation::Extended(op) => op.mnemonic,
+        }
+    }
+}
+
+impl<W: Word> fmt::Display for Operand<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mode {
+            AddressingMode::AbsoluteAddress => write!(f, "[{}]", self.value),
+            AddressingMode::Immediate => write!(f, "#{}", self.value),
+            AddressingMode::BasePointerRelative if self.value < W::default() => {
+                write!(f, "[bp-{}]", self.value.unsigned_abs())
+            }
+            AddressingMode::BasePointerRelative => write!(f, "[bp+{}]", self.value),
+        }
+    }
+}
+
+impl<W: Word> Operands<W> {
+    pub fn new() -> Self {
+        Operands {
+            items: [Operand {
+                mode: AddressingMode::Immediate,
+                value: W::default(),
+            }; MAX_OPERANDS],
+            len: 0,
+        }
+    }
+
+    /// Panics if there are already `MAX_OPERANDS` operands.
+    pub fn push(&mut self, operand: Operand<W>) {
+        self.items[self.len] = operand;
+        self.len += 1;
+    }
+}
+
+impl<W: Word> Default for Operands<W> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<W> Deref for Operands<W> {
+    type Target = [Operand<W>];
+
+    #[inline]
+    fn deref(&self) -> &[Operand<W>] {
+        &self.items[..self.len]
+    }
+}
+
+impl<W: fmt::Debug> fmt::Debug for Operands<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<W: PartialEq> PartialEq for Operands<W> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<W: Eq> Eq for Operands<W> {}
+
+impl<W: Word> FromIterator<Operand<W>> for Operands<W> {
+    fn from_iter<T: IntoIterator<Item = Operand<W>>>(iter: T) -> Self {
+        let mut operands = Operands::new();
+        for operand in iter {
+            operands.push(operand);
+        }
+        operands
+    }
+}
+
+impl<'a, W> IntoIterator for &'a Operands<W> {
+    type Item = &'a Operand<W>;
+    type IntoIter = std::slice::Iter<'a, Operand<W>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<W: Word> Opcode<W> {
+    /// Number of tape words the instruction occupies, including the opcode word itself.
+    #[inline]
+    pub fn size(&self) -> usize {
+        1 + self.operands.len()
+    }
+
+    /// Encodes the instruction back into tape words, opcode word first.
+    pub fn encode(&self) -> Vec<W> {
+        let mut word = self.operation.code();
+        let mut scale = 100;
+        for operand in &self.operands {
+            word += scale * operand.mode.digit();
+            scale *= 10;
+        }
+
+        let mut words = vec![W::from_isize(word as isize)];
+        words.extend(self.operands.iter().map(|op| op.value));
+        words
+    }
+}
+
+impl<W: Word> fmt::Display for Opcode<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.operation.mnemonic())?;
+        for (i, operand) in self.operands.iter().enumerate() {
+            let sep = if i == 0 { " " } else { ", " };
+            write!(f, "{}{}", sep, operand)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the instruction at `pc`, pulling the opcode word and then each operand from `fetch`.
+pub fn decode_opcode<W: Word>(
+    pc: isize,
+    fetch: impl FnMut() -> IntcodeResult<W>,
+) -> IntcodeResult<Opcode<W>> {
+    decode_extended_opcode(pc, None, fetch)
+}
+
+/// Like `decode_opcode`, but also recognises the operations registered in `extensions`.
+pub fn decode_extended_opcode<W: Word>(
+    pc: isize,
+    extensions: Option<&ExtensionRegistry<W>>,
+    mut fetch: impl FnMut() -> IntcodeResult<W>,
+) -> IntcodeResult<Opcode<W>> {
+    // Any word too wide for an `isize` is out of range as an opcode anyway.
+    let opcode = saturate(fetch()?);
+    if opcode < 0 {
+        return Err(IntcodeError::NegativeOpcode { pc, opcode });
+    }
+
+    if opcode >= 100_000 {
+        // There is no fourth operand, so anything past the third mode digit is bogus.
+        return Err(IntcodeError::InvalidAddressingMode {
+            pc,
+            opcode,
+            operand: 3,
+            mode: opcode as usize / 100_000,
+        });
+    }
+
+    let code = opcode as usize % 100;
+    let operation = Operation::from_code(code)
+        .or_else(|| extensions.and_then(|registry| registry.operation(code)));
+    let operation = match operation {
+        Some(operation) => operation,
+        None => return Err(IntcodeError::InvalidOpcodeOperation { pc, opcode }),
+    };
+
+    let mut modes = opcode as usize / 100;
+    let mut operands = Operands::new();
+    for i in 0..operation.operand_count() {
+        let digit = modes % 10;
+        modes /= 10;
+        let mode = match parse_addressing_mode(digit) {
+            Some(mode) => mode,
+            None => {
+                return Err(IntcodeError::InvalidAddressingMode {
+                    pc,
+                    opcode,
+                    operand: i,
+                    mode: digit,
+                })
+            }
+        };
+        let value = fetch()?;
+        operands.push(Operand { mode, value });
+    }
+
+    Ok(Opcode {
+        operation,
+        operands,
+    })
+}
+
+impl<W: Word> Program<W> {
+    /// Decodes the instruction starting at every address of `tape`. Words that don't decode,
+    /// such as data, are left to be decoded if they are ever executed.
+    pub fn new(tape: Vec<W>) -> Self {
+        let decoded = (0..tape.len())
+            .map(|pc| {
+                let default = std::iter::repeat(W::default());
+                let mut words = tape[pc..].iter().copied().chain(default);
+                let word = saturate(tape[pc]);
+                let opcode = decode_opcode(pc as isize, || Ok(words.next().unwrap())).ok()?;
+                Some(Instruction {
+                    pc: pc as isize,
+                    word,
+                    opcode,
+                })
+            })
+            .collect();
+        Program {
             tape,
+            decoded: Arc::new(decoded),
+        }
+    }
+
+    pub fn tape(&self) -> &Vec<W> {
+        &self.tape
+    }
+}
+
+impl<I: IntcodeInput<W>, O: IntcodeOutput<W>, W: Word> IntcodeMachine<I, O, NoHook, W> {
+    pub fn new_io(tape: Vec<W>, input: I, output: O) -> Self {
+        let decode_cache = Arc::new(vec![None; tape.len()]);
+        Self::build(tape, input, output, decode_cache)
+    }
+
+    /// Like `new_io`, but shares `program`'s decoded instructions instead of decoding them
+    /// again.
+    pub fn from_program_io(program: &Program<W>, input: I, output: O) -> Self {
+        Self::build(program.tape.clone(), input, output, program.decoded.clone())
+    }
+
+    fn build(tape: Vec<W>, input: I, output: O, decode_cache: DecodeCache<W>) -> Self {
+        IntcodeMachine {
+            code_len: tape.len(),
+            memory: Memory::new(tape),
             input,
             output,
             pc: 0,
             bp: 0,
+            instructions: 0,
+            output_batch: None,
+            unyielded_outputs: 0,
+            decode_cache: Some(decode_cache),
+            arithmetic: Arithmetic::default(),
+            extensions: None,
+            hook: NoHook,
         }
     }
+}
+
+type SharedStream<W> = Rc<RefCell<VecDeque<W>>>;
+
+impl<W: Word> IntcodeMachine<SharedStream<W>, SharedStream<W>, NoHook, W> {
+    pub fn new(tape: Vec<W>) -> Self {
+        Self::new_io(tape, SharedStream::default(), SharedStream::default())
+    }
 
-    pub fn new(tape: Tape) -> Self {
-        Self::new_io(tape, new_stream_ref(), new_stream_ref())
+    pub fn from_program(program: &Program<W>) -> Self {
+        Self::from_program_io(program, SharedStream::default(), SharedStream::default())
     }
 
-    fn verify_addr(&mut self, addr: isize) -> IntcodeResult<usize> {
-        if addr < 0 {
-            return Err(IntcodeError::NegativeAddress);
+    /// Builds a machine with its own streams from a snapshot.
+    pub fn from_snapshot(snapshot: &MachineSnapshot<W>) -> Self {
+        let mut machine = Self::new(Vec::new());
+        machine.code_len = snapshot.memory.dense().len();
+        machine.restore(snapshot);
+        machine
+    }
+}
+
+impl<H: IntcodeHook<W>, W: Word> IntcodeMachine<SharedStream<W>, SharedStream<W>, H, W> {
+    pub fn snapshot(&self) -> MachineSnapshot<W> {
+        MachineSnapshot {
+            memory: self.memory.clone(),
+            pc: self.pc,
+            bp: self.bp,
+            input: self.input.borrow().clone(),
+            output: self.output.borrow().clone(),
+            instructions: self.instructions,
         }
+    }
 
-        let addr = addr as usize;
-        if addr >= self.tape.len() {
-            self.tape.resize(addr + 1, 0);
+    /// Rewinds the machine to `snapshot`. Stream contents are replaced in place, so anything
+    /// sharing the machine's streams sees the restored contents too.
+    pub fn restore(&mut self, snapshot: &MachineSnapshot<W>) {
+        // The memory limit is a property of the machine rather than of its state.
+        let limit = self.memory.limit();
+        self.memory.clone_from(&snapshot.memory);
+        self.memory.set_limit(limit);
+        self.pc = snapshot.pc;
+        self.bp = snapshot.bp;
+        self.input.borrow_mut().clone_from(&snapshot.input);
+        self.output.borrow_mut().clone_from(&snapshot.output);
+        self.instructions = snapshot.instructions;
+        self.clear_decode_cache();
+    }
+
+    /// Clones the machine into an independent one with its own copies of both streams.
+    pub fn fork(&self) -> Self
+    where
+        H: Clone,
+    {
+        IntcodeMachine {
+            memory: self.memory.clone(),
+            input: Rc::new(RefCell::new(self.input.borrow().clone())),
+            output: Rc::new(RefCell::new(self.output.borrow().clone())),
+            pc: self.pc,
+            bp: self.bp,
+            instructions: self.instructions,
+            output_batch: self.output_batch,
+            unyielded_outputs: self.unyielded_outputs,
+            decode_cache: self.decode_cache.clone(),
+            code_len: self.code_len,
+            arithmetic: self.arithmetic,
+            extensions: self.extensions.clone(),
+            hook: self.hook.clone(),
         }
-        Ok(addr)
     }
+}
 
-    pub fn read_addr(&mut self, addr: isize) -> IntcodeResult<isize> {
-        let addr = self.verify_addr(addr)?;
-        Ok(self.tape[addr])
+impl<I: IntcodeInput<W>, O: IntcodeOutput<W>, H: IntcodeHook<W>, W: Word>
+    IntcodeMachine<I, O, H, W>
+{
+    /// Replaces the machine's hook, keeping all other state.
+    pub fn with_hook<T: IntcodeHook<W>>(self, hook: T) -> IntcodeMachine<I, O, T, W> {
+        IntcodeMachine {
+            memory: self.memory,
+            input: self.input,
+            output: self.output,
+            pc: self.pc,
+            bp: self.bp,
+            instructions: self.instructions,
+            output_batch: self.output_batch,
+            unyielded_outputs: self.unyielded_outputs,
+            decode_cache: self.decode_cache,
+            code_len: self.code_len,
+            arithmetic: self.arithmetic,
+            extensions: self.extensions.clone(),
+            hook,
+        }
     }
 
-    fn write_addr(&mut self, addr: isize, value: isize) -> IntcodeResult<()> {
-        let addr = self.verify_addr(addr)?;
-        self.tape[addr] = value;
-        Ok(())
+    pub fn pc(&self) -> isize {
+        self.pc
     }
 
-    fn read_pc(&mut self) -> IntcodeResult<isize> {
-        let value = self.read_addr(self.pc)?;
-        self.pc += 1;
-        Ok(value)
+    pub fn bp(&self) -> isize {
+        self.bp
     }
 
-    fn read_opcode(&mut self) -> IntcodeResult<Opcode> {
-        let opcode = self.read_pc()?;
-        if opcode < 0 {
-            return Err(IntcodeError::NegativeOpcode);
-        }
+    /// Makes the machine stop with `StopStatus::OutputReady` right after every `count`-th
+    /// output, e.g. 3 to get one stop per (address, x, y) packet. `None` turns this off.
+    pub fn yield_on_output(&mut self, count: Option<usize>) {
+        self.output_batch = count.filter(|&count| count > 0);
+        self.unyielded_outputs = 0;
+    }
+
+    /// Outputs produced since the last `StopStatus::OutputReady` stop.
+    fn unyielded_outputs(&self) -> usize {
+        self.unyielded_outputs
+    }
+
+    /// Puts the registers back to where they were before the most recent instruction. The
+    /// caller is responsible for undoing the instruction's effects.
+    fn rewind(&mut self, pc: isize, bp: isize, unyielded_outputs: usize) {
+        self.pc = pc;
+        self.bp = bp;
+        self.unyielded_outputs = unyielded_outputs;
+        self.instructions -= 1;
+    }
 
-        let mut digits = digits(opcode as usize, 10);
-        digits.extend(vec![0; 5 - digits.len()]);
+    /// Number of instructions executed to completion so far. Blocked inputs, halts and
+    /// faulting instructions are not counted.
+    pub fn instruction_count(&self) -> usize {
+        self.instructions
+    }
+
+    pub fn arithmetic(&self) -> Arithmetic {
+        self.arithmetic
+    }
+
+    pub fn set_arithmetic(&mut self, arithmetic: Arithmetic) {
+        self.arithmetic = arithmetic;
+    }
 
-        let (operation, operand_count) = match 10 * digits[1] + digits[0] {
-            1 => (Operation::Add, 3),
-            2 => (Operation::Multiply, 3),
-            3 => (Operation::Input, 1),
-            4 => (Operation::Output, 1),
-            5 => (Operation::JumpTrue, 2),
-            6 => (Operation::JumpFalse, 2),
-            7 => (Operation::LessThan, 3),
-            8 => (Operation::Equals, 3),
-            9 => (Operation::AdjustBasePointer, 1),
-            99 => (Operation::Halt, 0),
-            _ => return Err(IntcodeError::InvalidOpcodeOperation),
+    /// Installs the custom opcodes the program may use, replacing any previous set.
+    pub fn set_extensions(&mut self, extensions: Option<Arc<ExtensionRegistry<W>>>) {
+        self.extensions = extensions;
+        self.clear_decode_cache();
+    }
+
+    /// Decodes the instruction at `addr`, including extensions, without executing it.
+    pub fn decode_at(&self, addr: usize) -> IntcodeResult<Opcode<W>> {
+        let mut cursor = addr;
+        decode_extended_opcode(addr as isize, self.extensions.as_deref(), || {
+            let value = self.memory.get(cursor);
+            cursor += 1;
+            Ok(value)
+        })
+    }
+
+    pub fn memory(&self) -> &Memory<W> {
+        &self.memory
+    }
+
+    /// Caps how many words of memory the program may use; a write that needs more fails
+    /// with `IntcodeError::MemoryLimitExceeded`. `None`, the default, means no limit.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory.set_limit(limit);
+    }
+
+    /// Turns the decode cache on or off; it is on by default. Decoded instructions are kept
+    /// by address and dropped whenever a write touches one of their words, so self-modifying
+    /// code still sees its own writes. Only the tape the machine was built from is cached, so
+    /// the cache is at most a fixed multiple of the program's size. Turning it off only costs
+    /// speed.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache = match self.decode_cache.take() {
+            Some(cache) if enabled => Some(cache),
+            None if enabled => Some(Arc::new(vec![None; self.code_len])),
+            _ => None,
         };
+    }
 
-        let mut operands = Vec::<Operand>::new();
-        for i in 0..operand_count {
-            let mode = parse_addressing_mode(digits[2 + i])?;
-            let value = self.read_pc()?;
-            operands.push(Operand { mode, value });
+    fn clear_decode_cache(&mut self) {
+        if self.decode_cache.is_some() {
+            self.decode_cache = Some(Arc::new(vec![None; self.code_len]));
         }
+    }
+
+    /// Brings every cached instruction that covers `addr` up to date with its new `value`. A
+    /// rewritten operand is patched in place, which is what re-decoding would give; a rewritten
+    /// opcode word drops the instruction.
+    fn update_decoded(&mut self, addr: usize, value: W) {
+        if let Some(cache) = &mut self.decode_cache {
+            let first = addr.saturating_sub(MAX_OPERANDS);
+            let end = cache.len().min(addr + 1);
+            let stale = |start: usize, entry: &Option<Instruction<W>>| match entry {
+                Some(_) if start == addr => true,
+                Some(insn) if start + insn.opcode.size() > addr => {
+                    insn.opcode.operands[addr - start - 1].value != value
+                }
+                _ => false,
+            };
+            let any_stale = match cache.get(first..end) {
+                Some(entries) => (first..).zip(entries).any(|(start, e)| stale(start, e)),
+                None => false,
+            };
+            // Only copy a shared cache when something in it changes.
+            if any_stale {
+                let entries = &mut Arc::make_mut(cache)[first..end];
+                for (start, entry) in (first..).zip(entries) {
+                    if !stale(start, entry) {
+                        continue;
+                    }
+                    match entry {
+                        Some(insn) if start != addr => {
+                            insn.opcode.operands.items[addr - start - 1].value = value
+                        }
+                        _ => *entry = None,
+                    }
+                }
+            }
+        }
+    }
+
+    fn verify_addr(&self, addr: isize) -> Option<usize> {
+        if addr < 0 {
+            None
+        } else {
+            Some(addr as usize)
+        }
+    }
 
-        Ok(Opcode {
-            operation,
-            operands,
+    fn limit_error(&self, pc: isize, e: LimitExceeded) -> IntcodeError {
+        IntcodeError::MemoryLimitExceeded {
+            pc,
+            addr: e.addr,
+            limit: e.limit,
+        }
+    }
+
+    fn external_addr(&self, addr: isize) -> IntcodeResult<usize> {
+        self.verify_addr(addr).ok_or(IntcodeError::NegativeAddress {
+            pc: self.pc,
+            opcode: None,
+            operand: None,
+            addr,
         })
     }
 
-    fn load(&mut self, op: &Operand) -> IntcodeResult<isize> {
-        match op.mode {
-            AddressingMode::AbsoluteAddress => Ok(self.read_addr(op.value)?),
-            AddressingMode::Immediate => Ok(op.value),
-            AddressingMode::BasePointerRelative => Ok(self.read_addr(self.bp + op.value)?),
+    pub fn read_addr(&mut self, addr: isize) -> IntcodeResult<W> {
+        let addr = self.external_addr(addr)?;
+        Ok(self.memory.get(addr))
+    }
+
+    pub fn write_addr(&mut self, addr: isize, value: W) -> IntcodeResult<()> {
+        let addr = self.external_addr(addr)?;
+        self.memory
+            .set(addr, value)
+            .map_err(|e| self.limit_error(self.pc, e))?;
+        self.update_decoded(addr, value);
+        Ok(())
+    }
+
+    /// Checks an address computed by an instruction. Wider words can hold addresses past
+    /// `isize::MAX`, which fault with `IntcodeError::Overflow`.
+    #[inline]
+    fn operand_addr(&self, insn: &Instruction<W>, index: usize, addr: W) -> IntcodeResult<usize> {
+        if addr < W::default() {
+            return Err(IntcodeError::NegativeAddress {
+                pc: insn.pc,
+                opcode: Some(insn.word),
+                operand: Some(index),
+                addr: saturate(addr),
+            });
+        }
+        match addr.to_isize() {
+            Some(addr) => Ok(addr as usize),
+            None => Err(Self::overflow(insn)),
         }
     }
 
-    fn store(&mut self, op: &Operand, value: isize) -> IntcodeResult<()> {
-        match op.mode {
-            AddressingMode::AbsoluteAddress => Ok(self.write_addr(op.value, value)?),
-            AddressingMode::BasePointerRelative => Ok(self.write_addr(self.bp + op.value, value)?),
-            AddressingMode::Immediate => Err(IntcodeError::InvalidStoreAddressingMode),
+    /// Decodes the instruction at the pc and moves past it, caching it if the cache is on.
+    fn decode_instruction(&mut self) -> IntcodeResult<Instruction<W>> {
+        let pc = self.pc;
+        let word = saturate(self.read_addr(pc)?);
+        let opcode = self.decode_at(pc as usize)?;
+        let insn = Instruction { pc, word, opcode };
+        self.pc = pc
+            .checked_add(opcode.size() as isize)
+            .ok_or_else(|| Self::overflow(&insn))?;
+        let index = pc as usize;
+        if let Some(cache) = &mut self.decode_cache {
+            if index < cache.len() {
+                Arc::make_mut(cache)[index] = Some(insn);
+            }
+        }
+        Ok(insn)
+    }
+
+    fn overflow(insn: &Instruction<W>) -> IntcodeError {
+        IntcodeError::Overflow {
+            pc: insn.pc,
+            opcode: insn.word,
         }
     }
 
-    fn jump_conditional(&mut self, condition: bool, target: isize) -> IntcodeResult<()> {
+    #[inline]
+    fn add(&self, insn: &Instruction<W>, a: W, b: W) -> IntcodeResult<W> {
+        match self.arithmetic {
+            Arithmetic::Checked => a.checked_add(b).ok_or_else(|| Self::overflow(insn)),
+            Arithmetic::Wrapping => Ok(a.wrapping_add(b)),
+        }
+    }
+
+    #[inline]
+    fn mul(&self, insn: &Instruction<W>, a: W, b: W) -> IntcodeResult<W> {
+        match self.arithmetic {
+            Arithmetic::Checked => a.checked_mul(b).ok_or_else(|| Self::overflow(insn)),
+            Arithmetic::Wrapping => Ok(a.wrapping_mul(b)),
+        }
+    }
+
+    #[inline]
+    fn load(&mut self, insn: &Instruction<W>, index: usize) -> IntcodeResult<W> {
+        let op = insn.opcode.operands[index];
+        let addr = match op.mode {
+            AddressingMode::AbsoluteAddress => op.value,
+            AddressingMode::Immediate => return Ok(op.value),
+            AddressingMode::BasePointerRelative => {
+                self.add(insn, W::from_isize(self.bp), op.value)?
+            }
+        };
+        let addr = self.operand_addr(insn, index, addr)?;
+        let value = self.memory.get(addr);
+        self.hook.on_read(addr, value);
+        Ok(value)
+    }
+
+    #[inline]
+    fn store(&mut self, insn: &Instruction<W>, index: usize, value: W) -> IntcodeResult<()> {
+        let op = insn.opcode.operands[index];
+        let addr = match op.mode {
+            AddressingMode::AbsoluteAddress => op.value,
+            AddressingMode::BasePointerRelative => {
+                self.add(insn, W::from_isize(self.bp), op.value)?
+            }
+            AddressingMode::Immediate => {
+                return Err(IntcodeError::InvalidStoreAddressingMode {
+                    pc: insn.pc,
+                    opcode: insn.word,
+                    operand: index,
+                })
+            }
+        };
+        let addr = self.operand_addr(insn, index, addr)?;
+        let old = self
+            .memory
+            .set(addr, value)
+            .map_err(|e| self.limit_error(insn.pc, e))?;
+        self.hook.on_write(addr, old, value);
+        if old != value {
+            self.update_decoded(addr, value);
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn jump_conditional(&mut self, insn: &Instruction<W>, condition: bool) -> IntcodeResult<()> {
+        let target = self.load(insn, 1)?;
         if condition {
-            self.verify_addr(target)?;
-            self.pc = target;
+            self.pc = self.operand_addr(insn, 1, target)? as isize;
         }
         Ok(())
     }
 
-    fn tick(&mut self) -> IntcodeResult<Option<StopStatus>> {
+    /// Executes a single instruction. Returns the stop status if the machine cannot proceed,
+    /// in which case the pc is left on the halting or blocked instruction. `OutputReady` is
+    /// the exception: the output instruction has completed and the pc has moved past it.
+    pub fn tick(&mut self) -> IntcodeResult<Option<StopStatus>> {
         let start_pc = self.pc;
-        let opcode = self.read_opcode()?;
+        let result = self.execute();
+        match result {
+            Ok(None) | Ok(Some(StopStatus::OutputReady)) => self.instructions += 1,
+            Ok(Some(_)) => (),
+            // Leave the machine parked on the faulting instruction.
+            Err(_) => self.pc = start_pc,
+        }
+        result
+    }
+
+    #[inline]
+    fn execute(&mut self) -> IntcodeResult<Option<StopStatus>> {
+        let cached = match &self.decode_cache {
+            Some(cache) => cache.get(self.pc as usize),
+            None => None,
+        };
+        match cached {
+            Some(Some(insn)) => {
+                let insn = *insn;
+                self.pc += insn.opcode.size() as isize;
+                self.execute_instruction(&insn)
+            }
+            _ => {
+                let insn = self.decode_instruction()?;
+                self.execute_instruction(&insn)
+            }
+        }
+    }
+
+    #[inline]
+    fn execute_instruction(&mut self, insn: &Instruction<W>) -> IntcodeResult<Option<StopStatus>> {
+        let input = match insn.opcode.operation {
+            Operation::Input => match self.input.read() {
+                Some(value) => Some(value),
+                None => {
+                    self.pc = insn.pc;
+                    return Ok(Some(StopStatus::BlockedOnInput));
+                }
+            },
+            _ => None,
+        };
+        self.hook.on_instruction(insn.pc, &insn.opcode);
 
-        match opcode.operation {
+        match insn.opcode.operation {
             Operation::Add => {
-                let value = self.load(&opcode.operands[0])? + self.load(&opcode.operands[1])?;
-                self.store(&opcode.operands[2], value)?;
+                let (a, b) = (self.load(insn, 0)?, self.load(insn, 1)?);
+                let value = self.add(insn, a, b)?;
+                self.store(insn, 2, value)?;
             }
             Operation::Multiply => {
-                let value = self.load(&opcode.operands[0])? * self.load(&opcode.operands[1])?;
-                self.store(&opcode.operands[2], value)?;
+                let (a, b) = (self.load(insn, 0)?, self.load(insn, 1)?);
+                let value = self.mul(insn, a, b)?;
+                self.store(insn, 2, value)?;
             }
             Operation::Input => {
-                let input = self.input.borrow_mut().pop_front();
-                match input {
-                    Some(value) => self.store(&opcode.operands[0], value)?,
-                    None => {
-                        self.pc = start_pc;
-                        return Ok(Some(StopStatus::BlockedOnInput));
-                    }
-                };
+                if let Some(value) = input {
+                    self.hook.on_input(value);
+                    self.store(insn, 0, value)?;
+                }
             }
             Operation::Output => {
-                let value = self.load(&opcode.operands[0])?;
-                self.output.borrow_mut().push_back(value);
+                let value = self.load(insn, 0)?;
+                self.hook.on_output(value);
+                self.output.write(value);
+
+                if let Some(batch) = self.output_batch {
+                    self.unyielded_outputs += 1;
+                    if self.unyielded_outputs == batch {
+                        self.unyielded_outputs = 0;
+                        return Ok(Some(StopStatus::OutputReady));
+                    }
+                }
             }
             Operation::JumpTrue => {
-                let condition = self.load(&opcode.operands[0])?;
-                let target = self.load(&opcode.operands[1])?;
-                self.jump_conditional(condition != 0, target)?;
+                let condition = self.load(insn, 0)?;
+                self.jump_conditional(insn, condition != W::default())?;
             }
             Operation::JumpFalse => {
-                let condition = self.load(&opcode.operands[0])?;
-                let target = self.load(&opcode.operands[1])?;
-                self.jump_conditional(condition == 0, target)?;
+                let condition = self.load(insn, 0)?;
+                self.jump_conditional(insn, condition == W::default())?;
             }
             Operation::LessThan => {
-                let value = self.load(&opcode.operands[0])? < self.load(&opcode.operands[1])?;
-                self.store(&opcode.operands[2], value as isize)?;
+                let value = self.load(insn, 0)? < self.load(insn, 1)?;
+                self.store(insn, 2, W::from_isize(value as isize))?;
             }
             Operation::Equals => {
-                let value = self.load(&opcode.operands[0])? == self.load(&opcode.operands[1])?;
-                self.store(&opcode.operands[2], value as isize)?;
+                let value = self.load(insn, 0)? == self.load(insn, 1)?;
+                self.store(insn, 2, W::from_isize(value as isize))?;
             }
             Operation::AdjustBasePointer => {
-                let newbp = self.bp + self.load(&opcode.operands[0])?;
-                self.verify_addr(newbp)?;
-                self.bp = newbp;
+                let offset = self.load(insn, 0)?;
+                let newbp = self.add(insn, W::from_isize(self.bp), offset)?;
+                self.bp = self.operand_addr(insn, 0, newbp)? as isize;
             }
             Operation::Halt => {
-                self.pc = start_pc;
+                self.pc = insn.pc;
                 return Ok(Some(StopStatus::Halted));
             }
+            Operation::Extended(op) => return self.execute_extension(insn, op),
         };
 
         Ok(None)
     }
 
+    fn execute_extension(
+        &mut self,
+        insn: &Instruction<W>,
+        op: ExtendedOperation,
+    ) -> IntcodeResult<Option<StopStatus>> {
+        let inputs = op.operand_count as usize - op.stores_result as usize;
+        let mut args = [W::default(); MAX_OPERANDS];
+        for (index, arg) in args.iter_mut().enumerate().take(inputs) {
+            *arg = self.load(insn, index)?;
+        }
+
+        let fail = |message: String| IntcodeError::ExtensionFailed {
+            pc: insn.pc,
+            opcode: insn.word,
+            message,
+        };
+        let call = ExtensionCall {
+            pc: insn.pc,
+            bp: self.bp,
+            args: &args[..inputs],
+            memory: &self.memory,
+        };
+        let action = match self
+            .extensions
+            .as_ref()
+            .and_then(|r| r.handler(op.code as usize))
+        {
+            Some(handler) => handler(&call).map_err(fail)?,
+            None => return Err(fail(format!("{} is not registered", op.mnemonic))),
+        };
+
+        match action {
+            ExtensionAction::Continue => (),
+            ExtensionAction::Store(value) if op.stores_result => self.store(insn, inputs, value)?,
+            ExtensionAction::Store(_) => {
+                return Err(fail(format!("{} has no destination operand", op.mnemonic)))
+            }
+            ExtensionAction::Jump(target) => {
+                self.pc = self.operand_addr(insn, 0, target)? as isize;
+            }
+            ExtensionAction::Halt => {
+                self.pc = insn.pc;
+                return Ok(Some(StopStatus::Halted));
+            }
+        }
+        Ok(None)
+    }
+
     pub fn run(&mut self) -> IntcodeResult<StopStatus> {
         loop {
             match self.tick() {
@@ -248,10 +1321,24 @@ impl IntcodeMachine {
         }
     }
 
+    /// Like `run`, but gives up with `StopStatus::BudgetExhausted` after `budget` instructions.
+    /// The machine can be resumed afterwards.
+    pub fn run_for(&mut self, budget: usize) -> IntcodeResult<StopStatus> {
+        for _ in 0..budget {
+            if let Some(status) = self.tick()? {
+                return Ok(status);
+            }
+        }
+        Ok(StopStatus::BudgetExhausted)
+    }
+
     pub fn run_to_completion(&mut self) -> IntcodeResult<()> {
         match self.run()? {
             StopStatus::Halted => Ok(()),
-            _ => Err(IntcodeError::DidNotRunToCompletion),
+            status => Err(IntcodeError::DidNotRunToCompletion {
+                pc: self.pc,
+                status,
+            }),
         }
     }
 }
@@ -272,10 +1359,150 @@ pub fn new_stream_ref_from_iter(iter: impl IntoIterator<Item = isize>) -> Stream
     s
 }
 
+/// Like `try_parse_intcode_program`, but panics on malformed input.
 pub fn parse_intcode_program(input: &str) -> Tape {
-    input
-        .trim()
-        .split(',')
-        .map(|s| s.parse().unwrap())
-        .collect::<Tape>()
+    try_parse_intcode_program(input).unwrap_or_else(|e| panic!("Invalid Intcode program: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outputs(machine: &IntcodeMachine) -> Vec<isize> {
+        machine.output.borrow().iter().copied().collect()
+    }
+
+    #[test]
+    fn test_decode_cache_sees_rewritten_operands() {
+        // OUT #5, then overwrite its operand with 7 and go round once more.
+        let program = "104,5,1101,0,7,1,1005,20,16,1101,1,0,20,1106,0,0,99";
+        for &cache in &[true, false] {
+            let mut machine = IntcodeMachine::new(parse_intcode_program(program));
+            machine.set_decode_cache(cache);
+            assert_eq!(machine.run(), Ok(StopStatus::Halted));
+            assert_eq!(outputs(&machine), vec![5, 7]);
+        }
+    }
+
+    #[test]
+    fn test_decode_cache_sees_external_writes() {
+        // OUT [5] in an endless loop.
+        let mut machine = IntcodeMachine::new(parse_intcode_program("4,5,1105,1,0,42"));
+        assert_eq!(machine.run_for(3), Ok(StopStatus::BudgetExhausted));
+        machine.write_addr(1, 4).unwrap();
+        assert_eq!(machine.run_for(2), Ok(StopStatus::BudgetExhausted));
+        machine.write_addr(0, 104).unwrap();
+        assert_eq!(machine.run_for(2), Ok(StopStatus::BudgetExhausted));
+        assert_eq!(outputs(&machine), vec![42, 42, 0, 4]);
+    }
+
+    #[test]
+    fn test_program_shares_decoded_code_until_rewritten() {
+        let program = Program::new(parse_intcode_program("104,1,99"));
+        let mut machine = IntcodeMachine::from_program(&program);
+        assert_eq!(machine.run(), Ok(StopStatus::Halted));
+        assert_eq!(outputs(&machine), vec![1]);
+        assert!(Arc::ptr_eq(
+            machine.decode_cache.as_ref().unwrap(),
+            &program.decoded
+        ));
+
+        // The same self-modifying program as above, twice from one `Program`.
+        let program = "104,5,1101,0,7,1,1005,20,16,1101,1,0,20,1106,0,0,99";
+        let program = Program::new(parse_intcode_program(program));
+        for _ in 0..2 {
+            let mut machine = IntcodeMachine::from_program(&program);
+            assert_eq!(machine.run(), Ok(StopStatus::Halted));
+            assert_eq!(outputs(&machine), vec![5, 7]);
+        }
+        let first = program.decoded[0].unwrap();
+        assert_eq!(first.opcode.operands[0].value, 5);
+        // Data words that don't decode are left empty.
+        assert!(program.decoded[15].is_none());
+    }
+
+    #[test]
+    fn test_decode_cache_covers_only_the_tape() {
+        // Writes HLT at 2000 and jumps to it.
+        let mut machine = IntcodeMachine::new(parse_intcode_program("1101,99,0,2000,1105,1,2000"));
+        assert_eq!(machine.run(), Ok(StopStatus::Halted));
+        assert_eq!(machine.pc(), 2000);
+        assert!(machine.memory().dense().len() > 2000);
+        assert_eq!(machine.decode_cache.as_ref().unwrap().len(), 7);
+
+        machine.set_decode_cache(false);
+        machine.set_decode_cache(true);
+        assert_eq!(machine.decode_cache.as_ref().unwrap().len(), 7);
+    }
+
+    fn run_with(program: &str, arithmetic: Arithmetic) -> IntcodeResult<Vec<isize>> {
+        let mut machine = IntcodeMachine::new(parse_intcode_program(program));
+        machine.set_arithmetic(arithmetic);
+        machine.run_to_completion()?;
+        Ok(outputs(&machine))
+    }
+
+    #[test]
+    fn test_add_and_mul_overflow_in_each_mode() {
+        let add = format!("1101,{},1,0,4,0,99", isize::MAX);
+        let overflow = Err(IntcodeError::Overflow {
+            pc: 0,
+            opcode: 1101,
+        });
+        assert_eq!(run_with(&add, Arithmetic::Checked), overflow);
+        assert_eq!(run_with(&add, Arithmetic::Wrapping), Ok(vec![isize::MIN]));
+
+        let mul = format!("1102,{},2,0,4,0,99", 1isize << 62);
+        let overflow = Err(IntcodeError::Overflow {
+            pc: 0,
+            opcode: 1102,
+        });
+        assert_eq!(run_with(&mul, Arithmetic::Checked), overflow);
+        assert_eq!(run_with(&mul, Arithmetic::Wrapping), Ok(vec![isize::MIN]));
+    }
+
+    #[test]
+    fn test_base_pointer_overflow_in_each_mode() {
+        let arb = format!("109,{},109,1,99", isize::MAX);
+        assert_eq!(
+            run_with(&arb, Arithmetic::Checked),
+            Err(IntcodeError::Overflow { pc: 2, opcode: 109 })
+        );
+        assert_eq!(
+            run_with(&arb, Arithmetic::Wrapping),
+            Err(IntcodeError::NegativeAddress {
+                pc: 2,
+                opcode: Some(109),
+                operand: Some(0),
+                addr: isize::MIN,
+            })
+        );
+
+        // A relative operand past the end of the address space.
+        let relative = format!("109,{},204,1,99", isize::MAX);
+        assert_eq!(
+            run_with(&relative, Arithmetic::Checked),
+            Err(IntcodeError::Overflow { pc: 2, opcode: 204 })
+        );
+        assert_eq!(
+            run_with(&relative, Arithmetic::Wrapping),
+            Err(IntcodeError::NegativeAddress {
+                pc: 2,
+                opcode: Some(204),
+                operand: Some(0),
+                addr: isize::MIN,
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_cache_is_cleared_on_restore() {
+        let mut machine = IntcodeMachine::new(parse_intcode_program("104,1,1105,1,0"));
+        let snapshot = machine.snapshot();
+        machine.write_addr(1, 2).unwrap();
+        assert_eq!(machine.run_for(2), Ok(StopStatus::BudgetExhausted));
+        machine.restore(&snapshot);
+        assert_eq!(machine.run_for(2), Ok(StopStatus::BudgetExhausted));
+        assert_eq!(outputs(&machine), vec![1]);
+    }
 }
@@ -0,0 +1,178 @@
+//! A conversational wrapper for programs that talk ASCII: send a line, read the reply.
+
+use super::ports::{AsciiInput, AsciiOutput};
+use super::*;
+use std::io::{self, BufRead, Write};
+
+/// Everything the program printed since the previous exchange.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub text: String,
+    /// Outputs that aren't ASCII characters, typically the puzzle answer.
+    pub values: Vec<isize>,
+    /// `BlockedOnInput` when the program is waiting at a prompt.
+    pub status: StopStatus,
+}
+
+#[derive(Debug)]
+pub struct AsciiConsole<H = NoHook> {
+    pub machine: IntcodeMachine<AsciiInput, AsciiOutput, H>,
+}
+
+impl AsciiConsole {
+    pub fn new(tape: Tape) -> Self {
+        AsciiConsole {
+            machine: IntcodeMachine::new_io(tape, AsciiInput::new(), AsciiOutput::new()),
+        }
+    }
+}
+
+impl<H: IntcodeHook> AsciiConsole<H> {
+    /// Replaces the underlying machine's hook, e.g. to record a session.
+    pub fn with_hook<T: IntcodeHook>(self, hook: T) -> AsciiConsole<T> {
+        AsciiConsole {
+            machine: self.machine.with_hook(hook),
+        }
+    }
+
+    /// Queues `line` and a newline. Nothing runs until the next `run`.
+    pub fn send_line(&mut self, line: &str) {
+        self.machine.input.push_line(line);
+    }
+
+    pub fn send_lines<'a>(&mut self, lines: impl IntoIterator<Item = &'a str>) {
+        for line in lines {
+            self.send_line(line);
+        }
+    }
+
+    fn take_response(&mut self, status: StopStatus) -> Response {
+        Response {
+            text: self.machine.output.take_text(),
+            values: std::mem::take(&mut self.machine.output.values),
+            status,
+        }
+    }
+
+    /// Runs until the program halts or waits for input it hasn't been sent.
+    pub fn run(&mut self) -> IntcodeResult<Response> {
+        self.machine.yield_on_output(None);
+        let status = self.machine.run()?;
+        Ok(self.take_response(status))
+    }
+
+    /// Runs until the output so far ends with `prompt`, the program halts or it waits for
+    /// input. Useful when several commands are queued and each reply is wanted separately.
+    pub fn run_until(&mut self, prompt: &str) -> IntcodeResult<Response> {
+        self.machine.yield_on_output(Some(1));
+        let status = loop {
+            match self.machine.run() {
+                Ok(StopStatus::OutputReady) if !self.machine.output.text.ends_with(prompt) => (),
+                Ok(status) => break status,
+                Err(e) => {
+                    self.machine.yield_on_output(None);
+                    return Err(e);
+                }
+            }
+        };
+        self.machine.yield_on_output(None);
+        Ok(self.take_response(status))
+    }
+
+    /// Plays the program on the terminal: prints its output and sends each line typed in,
+    /// until it halts or stdin ends.
+    pub fn interact(&mut self) -> IntcodeResult<StopStatus> {
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        loop {
+            let response = self.run()?;
+            print!("{}", response.text);
+            for value in &response.values {
+                println!("{}", value);
+            }
+            let _ = io::stdout().flush();
+
+            if response.status == StopStatus::Halted {
+                return Ok(response.status);
+            }
+            match lines.next() {
+                Some(Ok(line)) => self.send_line(line.trim_end()),
+                _ => return Ok(response.status),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Prompts with "?", then echoes each character it reads, prompting again after every
+    /// newline. A '!' prints 1000 and halts.
+    const ECHO: &str = "104,63,104,10,3,100,4,100,1008,100,33,101,1005,101,25,\
+                        1008,100,10,101,1005,101,0,1105,1,4,104,1000,99";
+
+    fn console() -> AsciiConsole {
+        AsciiConsole::new(parse_intcode_program(ECHO))
+    }
+
+    #[test]
+    fn test_conversation() {
+        let mut console = console();
+        let response = console.run().unwrap();
+        assert_eq!(response.text, "?\n");
+        assert!(response.values.is_empty());
+        assert_eq!(response.status, StopStatus::BlockedOnInput);
+
+        console.send_line("hi");
+        let response = console.run().unwrap();
+        assert_eq!(response.text, "hi\n?\n");
+        assert_eq!(response.status, StopStatus::BlockedOnInput);
+
+        console.send_line("!");
+        assert_eq!(
+            console.run().unwrap(),
+            Response {
+                text: "!".to_string(),
+                values: vec![1000],
+                status: StopStatus::Halted,
+            }
+        );
+    }
+
+    #[test]
+    fn test_run_until_prompt() {
+        let mut console = console();
+        console.run().unwrap();
+        console.send_lines(vec!["a", "bc"]);
+
+        let response = console.run_until("?\n").unwrap();
+        assert_eq!(response.text, "a\n?\n");
+        assert_eq!(response.status, StopStatus::OutputReady);
+        let response = console.run_until("?\n").unwrap();
+        assert_eq!(response.text, "bc\n?\n");
+        // Nothing left to read, so the prompt is never printed again.
+        let response = console.run_until("?\n").unwrap();
+        assert_eq!(response.text, "");
+        assert_eq!(response.status, StopStatus::BlockedOnInput);
+    }
+
+    #[test]
+    fn test_run_until_halt() {
+        let mut console = console();
+        console.send_line("x!");
+        let response = console.run_until("never").unwrap();
+        assert_eq!(response.text, "?\nx!");
+        assert_eq!(response.values, vec![1000]);
+        assert_eq!(response.status, StopStatus::Halted);
+    }
+
+    #[test]
+    fn test_fault_resets_output_yield() {
+        let mut console = AsciiConsole::new(parse_intcode_program("104,65,104,66,0"));
+        assert!(console.run_until("C").is_err());
+        assert_eq!(console.machine.output.take_text(), "AB");
+        // A plain run after the fault runs straight to it again, not stopping on output.
+        assert!(console.run().is_err());
+    }
+}
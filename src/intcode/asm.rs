@@ -0,0 +1,388 @@
+//! Two-pass assembler for Intcode source text.
+//!
+//! ```text
+//! ; countdown from 10, printing each value
+//!         add #10, #0, [counter]
+//! loop:   out [counter]
+//!         add [counter], #-1, [counter]
+//!         jt [counter], #loop
+//!         hlt
+//! counter: .data 0
+//! ```
+//!
+//! Operands are `#imm`, `[abs]` or `[bp+n]`, where values may be integers, labels or
+//! `label+n`. Mnemonics are case-insensitive, so disassembled instructions reassemble as-is.
+
+use super::*;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmErrorKind {
+    UnknownMnemonic(String),
+    UnknownDirective(String),
+    WrongOperandCount { expected: usize, found: usize },
+    InvalidOperand(String),
+    InvalidValue(String),
+    ImmediateDestination,
+    InvalidLabel(String),
+    DuplicateLabel(String),
+    UndefinedLabel(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub kind: AsmErrorKind,
+}
+
+pub type AsmResult<T> = Result<T, AsmError>;
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            AsmErrorKind::UnknownMnemonic(m) => write!(f, "unknown mnemonic '{}'", m),
+            AsmErrorKind::UnknownDirective(d) => write!(f, "unknown directive '{}'", d),
+            AsmErrorKind::WrongOperandCount { expected, found } => {
+                write!(f, "expected {} operands, found {}", expected, found)
+            }
+            AsmErrorKind::InvalidOperand(op) => write!(f, "invalid operand '{}'", op),
+            AsmErrorKind::InvalidValue(v) => write!(f, "invalid value '{}'", v),
+            AsmErrorKind::ImmediateDestination => write!(f, "destination cannot be immediate"),
+            AsmErrorKind::InvalidLabel(l) => write!(f, "invalid label name '{}'", l),
+            AsmErrorKind::DuplicateLabel(l) => write!(f, "label '{}' defined twice", l),
+            AsmErrorKind::UndefinedLabel(l) => write!(f, "undefined label '{}'", l),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// An operand value that may still refer to a label.
+#[derive(Debug, Clone)]
+enum Value {
+    Literal(isize),
+    Label(String, isize),
+}
+
+#[derive(Debug)]
+enum Item {
+    Instruction(Operation, Vec<(AddressingMode, Value)>),
+    Data(Vec<Value>),
+}
+
+impl Item {
+    fn size(&self) -> usize {
+        match self {
+            Item::Instruction(_, operands) => 1 + operands.len(),
+            Item::Data(values) => values.len(),
+        }
+    }
+}
+
+fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') && name != "bp"
+}
+
+fn parse_value(text: &str) -> Result<Value, AsmErrorKind> {
+    let text = text.trim();
+    if let Ok(n) = text.parse::<isize>() {
+        return Ok(Value::Literal(n));
+    }
+
+    let (name, offset) = match text.find(['+', '-']) {
+        Some(i) => {
+            let offset = text[i..]
+                .replace(' ', "")
+                .trim_start_matches('+')
+                .parse::<isize>()
+                .map_err(|_| AsmErrorKind::InvalidValue(text.to_string()))?;
+            (text[..i].trim(), offset)
+        }
+        None => (text, 0),
+    };
+
+    if is_label_name(name) {
+        Ok(Value::Label(name.to_string(), offset))
+    } else {
+        Err(AsmErrorKind::InvalidValue(text.to_string()))
+    }
+}
+
+fn parse_operand(text: &str) -> Result<(AddressingMode, Value), AsmErrorKind> {
+    let text = text.trim();
+    if let Some(imm) = text.strip_prefix('#') {
+        return Ok((AddressingMode::Immediate, parse_value(imm)?));
+    }
+
+    let inner = text
+        .strip_prefix('[')
+        .and_then(|t| t.strip_suffix(']'))
+        .ok_or_else(|| AsmErrorKind::InvalidOperand(text.to_string()))?
+        .trim();
+
+    match inner.strip_prefix("bp") {
+        Some("") => Ok((AddressingMode::BasePointerRelative, Value::Literal(0))),
+        Some(rest) if rest.trim_start().starts_with(['+', '-']) => {
+            let rest = rest.replace(' ', "");
+            let value = match rest.strip_prefix('+') {
+                Some(positive) => parse_value(positive)?,
+                None => parse_value(&rest)?,
+            };
+            Ok((AddressingMode::BasePointerRelative, value))
+        }
+        _ => Ok((AddressingMode::AbsoluteAddress, parse_value(inner)?)),
+    }
+}
+
+fn parse_mnemonic(mnemonic: &str) -> Option<Operation> {
+    OPERATIONS
+        .iter()
+        .copied()
+        .find(|op| op.mnemonic().eq_ignore_ascii_case(mnemonic))
+}
+
+fn split_list(text: &str) -> Vec<&str> {
+    if text.trim().is_empty() {
+        Vec::new()
+    } else {
+        text.split(',').map(str::trim).collect()
+    }
+}
+
+fn parse_statement(text: &str) -> Result<Item, AsmErrorKind> {
+    let (head, rest) = match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], &text[i..]),
+        None => (text, ""),
+    };
+
+    if head.starts_with('.') {
+        return match head {
+            ".data" => {
+                let values = split_list(rest)
+                    .into_iter()
+                    .map(parse_value)
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Item::Data(values))
+            }
+            _ => Err(AsmErrorKind::UnknownDirective(head.to_string())),
+        };
+    }
+
+    let operation =
+        parse_mnemonic(head).ok_or_else(|| AsmErrorKind::UnknownMnemonic(head.to_string()))?;
+    let operands = split_list(rest)
+        .into_iter()
+        .map(parse_operand)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let expected = operation.operand_count();
+    if operands.len() != expected {
+        return Err(AsmErrorKind::WrongOperandCount {
+            expected,
+            found: operands.len(),
+        });
+    }
+    if operation.stores_result() && operands[expected - 1].0 == AddressingMode::Immediate {
+        return Err(AsmErrorKind::ImmediateDestination);
+    }
+
+    Ok(Item::Instruction(operation, operands))
+}
+
+fn resolve(value: &Value, labels: &HashMap<String, usize>) -> Result<isize, AsmErrorKind> {
+    match value {
+        Value::Literal(n) => Ok(*n),
+        Value::Label(name, offset) => match labels.get(name) {
+            Some(address) => (*address as isize)
+                .checked_add(*offset)
+                .ok_or_else(|| AsmErrorKind::InvalidValue(format!("{}{:+}", name, offset))),
+            None => Err(AsmErrorKind::UndefinedLabel(name.clone())),
+        },
+    }
+}
+
+pub fn assemble(source: &str) -> AsmResult<Tape> {
+    let mut items = Vec::<(usize, Item)>::new();
+    let mut labels = HashMap::<String, usize>::new();
+    let mut address = 0usize;
+
+    for (index, line) in source.lines().enumerate() {
+        let line_no = index + 1;
+        let error = |kind| AsmError {
+            line: line_no,
+            kind,
+        };
+
+        let mut text = line.split(';').next().unwrap().trim();
+        while let Some(colon) = text.find(':') {
+            let name = text[..colon].trim();
+            if !is_label_name(name) {
+                return Err(error(AsmErrorKind::InvalidLabel(name.to_string())));
+            }
+            if labels.insert(name.to_string(), address).is_some() {
+                return Err(error(AsmErrorKind::DuplicateLabel(name.to_string())));
+            }
+            text = text[colon + 1..].trim();
+        }
+
+        if text.is_empty() {
+            continue;
+        }
+
+        let item = parse_statement(text).map_err(error)?;
+        address += item.size();
+        items.push((line_no, item));
+    }
+
+    let mut tape = Tape::with_capacity(address);
+    for (line_no, item) in items {
+        let error = |kind| AsmError {
+            line: line_no,
+            kind,
+        };
+
+        match item {
+            Item::Instruction(operation, operands) => {
+                let operands = operands
+                    .iter()
+                    .map(|(mode, value)| {
+                        let value = resolve(value, &labels)?;
+                        Ok(Operand { mode: *mode, value })
+                    })
+                    .collect::<Result<Operands, _>>()
+                    .map_err(error)?;
+                let opcode = Opcode {
+                    operation,
+                    operands,
+                };
+                tape.extend(opcode.encode());
+            }
+            Item::Data(values) => {
+                for value in values {
+                    tape.push(resolve(&value, &labels).map_err(error)?);
+                }
+            }
+        }
+    }
+
+    Ok(tape)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::disasm::{disassemble, label_name};
+
+    const COUNTDOWN: &str = "\
+        ; countdown from 3, printing each value
+                add #3, #0, [counter]
+        loop:   out [counter]
+                add [counter], #-1, [counter]
+                jt [counter], #loop
+                hlt
+        counter: .data 0";
+
+    /// Turns a listing back into source, one statement per line with its label.
+    fn listing_source(tape: &Tape) -> String {
+        let listing = disassemble(tape);
+        let mut source = String::new();
+        for line in &listing.lines {
+            if listing.labels.contains(&line.address) {
+                source.push_str(&format!("{}: ", label_name(line.address)));
+            }
+            source.push_str(&listing.format_body(line));
+            source.push('\n');
+        }
+        source
+    }
+
+    #[test]
+    fn test_assemble_and_run() {
+        let tape = assemble(COUNTDOWN).unwrap();
+        assert_eq!(
+            tape,
+            vec![1101, 3, 0, 14, 4, 14, 1001, 14, -1, 14, 1005, 14, 4, 99, 0]
+        );
+        let mut machine = IntcodeMachine::new(tape);
+        assert_eq!(machine.run(), Ok(StopStatus::Halted));
+        assert_eq!(
+            machine.output.borrow().iter().copied().collect::<Vec<_>>(),
+            vec![3, 2, 1]
+        );
+    }
+
+    #[test]
+    fn test_round_trip_through_disassembler() {
+        let tapes = vec![
+            assemble(COUNTDOWN).unwrap(),
+            parse_intcode_program("109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99"),
+            parse_intcode_program("3,3,1105,-1,9,1101,0,0,12,4,12,99,1"),
+            vec![21101, 5, -7, 3, 204, isize::MIN, 99, 1, 2, 3],
+        ];
+        for tape in tapes {
+            let source = listing_source(&tape);
+            assert_eq!(assemble(&source), Ok(tape), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_operand_syntax() {
+        assert_eq!(
+            assemble("ADD [bp], [bp - 2], [bp+x+1]\nx: .data -3, x, x-1").unwrap(),
+            vec![22201, 0, -2, 5, -3, 4, 3]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |source: &str| assemble(source).unwrap_err();
+        assert_eq!(
+            error("hlt\nout #nowhere"),
+            AsmError {
+                line: 2,
+                kind: AsmErrorKind::UndefinedLabel("nowhere".to_string()),
+            }
+        );
+        assert_eq!(
+            error("add #1, #2, #3").kind,
+            AsmErrorKind::ImmediateDestination
+        );
+        assert_eq!(
+            error("out #1, #2").kind,
+            AsmErrorKind::WrongOperandCount {
+                expected: 1,
+                found: 2,
+            }
+        );
+        assert_eq!(
+            error("a: hlt\na: hlt").kind,
+            AsmErrorKind::DuplicateLabel("a".to_string())
+        );
+        assert_eq!(
+            error("jmp #0").kind,
+            AsmErrorKind::UnknownMnemonic("jmp".to_string())
+        );
+        assert_eq!(
+            error(".word 1").kind,
+            AsmErrorKind::UnknownDirective(".word".to_string())
+        );
+        assert_eq!(
+            error("out 5").kind,
+            AsmErrorKind::InvalidOperand("5".to_string())
+        );
+        assert_eq!(error("1x: hlt").line, 1);
+        assert_eq!(
+            error("hlt\nx: .data x+9223372036854775807"),
+            AsmError {
+                line: 2,
+                kind: AsmErrorKind::InvalidValue("x+9223372036854775807".to_string()),
+            }
+        );
+    }
+}
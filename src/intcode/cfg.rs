@@ -0,0 +1,356 @@
+//! Basic blocks and a control-flow graph recovered statically from a tape, along with the
+//! writes that patch code and the regions no traced path reaches.
+
+use super::disasm::{
+    is_never_taken, is_unconditional_transfer, jump_target, label_name, trace_code,
+};
+use super::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EdgeKind {
+    FallThrough,
+    Jump,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: usize,
+    /// One past the last word of the last instruction.
+    pub end: usize,
+    pub instructions: Vec<(usize, Opcode)>,
+    pub successors: Vec<(usize, EdgeKind)>,
+    /// Whether the block can also leave through a jump whose target is only known at run
+    /// time, typically a return.
+    pub indirect_exit: bool,
+}
+
+/// An instruction whose absolute destination operand lies inside traced code.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SelfModifyingWrite {
+    pub address: usize,
+    pub target: usize,
+    /// Start of the instruction being patched; `target - instruction` is 0 for the opcode word
+    /// and 1-3 for an operand.
+    pub instruction: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct ControlFlowGraph {
+    pub blocks: BTreeMap<usize, BasicBlock>,
+    pub self_modifying_writes: Vec<SelfModifyingWrite>,
+    /// Tape ranges that no traced path executes: data, or code only reached through computed
+    /// jumps.
+    pub unreached: Vec<Range<usize>>,
+}
+
+fn is_control_transfer(opcode: &Opcode) -> bool {
+    matches!(
+        opcode.operation,
+        Operation::JumpTrue | Operation::JumpFalse | Operation::Halt
+    )
+}
+
+fn block_successors(last: &Opcode, end: usize) -> (Vec<(usize, EdgeKind)>, bool) {
+    let mut successors = Vec::new();
+    let mut indirect = false;
+    match last.operation {
+        Operation::Halt => (),
+        Operation::JumpTrue | Operation::JumpFalse if !is_never_taken(last) => {
+            match jump_target(last) {
+                Some(target) => successors.push((target, EdgeKind::Jump)),
+                None => indirect = true,
+            }
+        }
+        _ => (),
+    }
+    if !is_unconditional_transfer(last) {
+        successors.push((end, EdgeKind::FallThrough));
+    }
+    (successors, indirect)
+}
+
+/// Builds the graph from the instructions `disasm::trace_code` reaches from pc 0.
+pub fn build_cfg(tape: &[isize]) -> ControlFlowGraph {
+    let (code, targets) = trace_code(tape);
+
+    let mut blocks = BTreeMap::<usize, BasicBlock>::new();
+    let mut current: Option<BasicBlock> = None;
+    for (address, opcode) in &code {
+        let split = match &current {
+            Some(block) => block.end != *address || targets.contains(address),
+            None => true,
+        };
+        if split {
+            if let Some(block) = current.take() {
+                blocks.insert(block.start, block);
+            }
+        }
+
+        let block = current.get_or_insert_with(|| BasicBlock {
+            start: *address,
+            end: *address,
+            instructions: Vec::new(),
+            successors: Vec::new(),
+            indirect_exit: false,
+        });
+        block.end = address + opcode.size();
+        block.instructions.push((*address, *opcode));
+        if is_control_transfer(opcode) {
+            blocks.insert(block.start, current.take().unwrap());
+        }
+    }
+    if let Some(block) = current {
+        blocks.insert(block.start, block);
+    }
+
+    let starts: BTreeSet<usize> = blocks.keys().copied().collect();
+    for block in blocks.values_mut() {
+        let (_, last) = block.instructions.last().unwrap();
+        let (successors, indirect) = block_successors(last, block.end);
+        block.successors = successors
+            .into_iter()
+            .filter(|(target, _)| starts.contains(target))
+            .collect();
+        block.indirect_exit = indirect;
+    }
+
+    let self_modifying_writes = find_self_modifying_writes(&code);
+    let unreached = find_unreached(&code, tape.len());
+    ControlFlowGraph {
+        blocks,
+        self_modifying_writes,
+        unreached,
+    }
+}
+
+fn find_self_modifying_writes(code: &[(usize, Opcode)]) -> Vec<SelfModifyingWrite> {
+    let by_address: BTreeMap<usize, &Opcode> = code.iter().map(|(a, op)| (*a, op)).collect();
+    let covering = |target: usize| {
+        by_address
+            .range(..=target)
+            .next_back()
+            .filter(|(start, opcode)| *start + opcode.size() > target)
+            .map(|(start, _)| *start)
+    };
+
+    let mut writes = Vec::new();
+    for (address, opcode) in code {
+        if !opcode.operation.stores_result() {
+            continue;
+        }
+        let destination = opcode.operands[opcode.operands.len() - 1];
+        if destination.mode != AddressingMode::AbsoluteAddress || destination.value < 0 {
+            continue;
+        }
+        let target = destination.value as usize;
+        if let Some(instruction) = covering(target) {
+            writes.push(SelfModifyingWrite {
+                address: *address,
+                target,
+                instruction,
+            });
+        }
+    }
+    writes
+}
+
+fn find_unreached(code: &[(usize, Opcode)], len: usize) -> Vec<Range<usize>> {
+    let mut unreached = Vec::new();
+    let mut address = 0;
+    for (start, opcode) in code {
+        if *start > address {
+            unreached.push(address..*start);
+        }
+        address = start + opcode.size();
+    }
+    if address < len {
+        unreached.push(address..len);
+    }
+    unreached
+}
+
+impl ControlFlowGraph {
+    pub fn predecessors(&self, start: usize) -> impl Iterator<Item = &BasicBlock> {
+        self.blocks
+            .values()
+            .filter(move |block| block.successors.iter().any(|(t, _)| *t == start))
+    }
+
+    /// Renders the graph in Graphviz DOT format, one box per block. Jumps are solid edges,
+    /// fall-throughs dashed, and computed jumps lead to a shared `?` node.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph intcode {\n");
+        dot.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+        for block in self.blocks.values() {
+            let mut label = format!("{}:\\l", label_name(block.start));
+            for (address, opcode) in &block.instructions {
+                label.push_str(&format!("{:>6}  {}\\l", address, opcode));
+            }
+            dot.push_str(&format!(
+                "    {} [label=\"{}\"];\n",
+                label_name(block.start),
+                label
+            ));
+        }
+
+        let mut any_indirect = false;
+        for block in self.blocks.values() {
+            for (target, kind) in &block.successors {
+                let style = match kind {
+                    EdgeKind::Jump => "solid",
+                    EdgeKind::FallThrough => "dashed",
+                };
+                dot.push_str(&format!(
+                    "    {} -> {} [style={}];\n",
+                    label_name(block.start),
+                    label_name(*target),
+                    style
+                ));
+            }
+            if block.indirect_exit {
+                any_indirect = true;
+                dot.push_str(&format!(
+                    "    {} -> indirect [style=dotted];\n",
+                    label_name(block.start)
+                ));
+            }
+        }
+        if any_indirect {
+            dot.push_str("    indirect [shape=ellipse, label=\"?\"];\n");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+impl fmt::Display for ControlFlowGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} basic blocks", self.blocks.len())?;
+        for block in self.blocks.values() {
+            let mut exits: Vec<String> = block
+                .successors
+                .iter()
+                .map(|(target, kind)| match kind {
+                    EdgeKind::Jump => label_name(*target),
+                    EdgeKind::FallThrough => format!("{} (fall through)", label_name(*target)),
+                })
+                .collect();
+            if block.indirect_exit {
+                exits.push("? (computed)".to_string());
+            }
+            writeln!(
+                f,
+                "  {}  {:>6}..{:<6} {:>3} instructions  -> {}",
+                label_name(block.start),
+                block.start,
+                block.end,
+                block.instructions.len(),
+                if exits.is_empty() {
+                    "halt".to_string()
+                } else {
+                    exits.join(", ")
+                }
+            )?;
+        }
+
+        writeln!(
+            f,
+            "{} self-modifying writes",
+            self.self_modifying_writes.len()
+        )?;
+        for write in &self.self_modifying_writes {
+            let offset = write.target - write.instruction;
+            let part = if offset == 0 {
+                "the opcode word".to_string()
+            } else {
+                format!("operand {}", offset - 1)
+            };
+            writeln!(
+                f,
+                "  {:>6}  writes [{}], {} of the instruction at {}",
+                write.address, write.target, part, write.instruction
+            )?;
+        }
+
+        let total: usize = self.unreached.iter().map(|r| r.len()).sum();
+        writeln!(f, "{} unreached words", total)?;
+        for range in &self.unreached {
+            writeln!(
+                f,
+                "  {:>6}..{:<6} {} words",
+                range.start,
+                range.end,
+                range.len()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loop() {
+        // Counts [20] up to 3, jumping back to 0 while it is below.
+        let cfg = build_cfg(&parse_intcode_program(
+            "1001,20,1,20,4,20,1007,20,3,21,1005,21,0,99",
+        ));
+        assert_eq!(cfg.blocks.keys().copied().collect::<Vec<_>>(), vec![0, 13]);
+        let body = &cfg.blocks[&0];
+        assert_eq!(body.end, 13);
+        assert_eq!(body.instructions.len(), 4);
+        assert_eq!(
+            body.successors,
+            vec![(0, EdgeKind::Jump), (13, EdgeKind::FallThrough)]
+        );
+        assert!(!body.indirect_exit);
+        assert!(cfg.blocks[&13].successors.is_empty());
+        assert_eq!(
+            cfg.predecessors(13).map(|b| b.start).collect::<Vec<_>>(),
+            vec![0]
+        );
+        assert!(cfg.self_modifying_writes.is_empty());
+        assert!(cfg.unreached.is_empty());
+
+        let dot = cfg.to_dot();
+        assert!(dot.contains("L0000 -> L0000 [style=solid];"), "{}", dot);
+        assert!(dot.contains("L0000 -> L0013 [style=dashed];"), "{}", dot);
+        assert!(!dot.contains("indirect"), "{}", dot);
+    }
+
+    #[test]
+    fn test_computed_jump() {
+        let cfg = build_cfg(&parse_intcode_program("2105,1,0"));
+        let block = &cfg.blocks[&0];
+        assert!(block.successors.is_empty());
+        assert!(block.indirect_exit);
+        assert!(cfg.to_dot().contains("L0000 -> indirect [style=dotted];"));
+        assert!(cfg.to_string().contains("-> ? (computed)"));
+    }
+
+    #[test]
+    fn test_self_modifying_write_and_unreached_words() {
+        // Overwrites the opcode of the ADD at 4; the words after the HLT are never run.
+        let cfg = build_cfg(&parse_intcode_program("1101,0,99,4,1101,0,0,9,99,7,7"));
+        assert_eq!(
+            cfg.self_modifying_writes,
+            vec![SelfModifyingWrite {
+                address: 0,
+                target: 4,
+                instruction: 4,
+            }]
+        );
+        assert_eq!(cfg.unreached, vec![9..11]);
+        let text = cfg.to_string();
+        assert!(
+            text.contains("writes [4], the opcode word of the instruction at 4"),
+            "{}",
+            text
+        );
+        assert!(text.contains("2 unreached words"), "{}", text);
+    }
+}
@@ -0,0 +1,124 @@
+//! `Send`-able I/O ports backed by `std::sync::mpsc`, so each machine can run on its own
+//! thread. A blocking port waits for input and only reports `BlockedOnInput` once every
+//! sender is gone; a non-blocking port behaves like a `StreamRef` and keeps the cooperative
+//! scheduling model working.
+
+use super::*;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+#[derive(Debug)]
+pub struct ChannelInput {
+    receiver: Receiver<isize>,
+    blocking: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChannelOutput {
+    sender: Sender<isize>,
+}
+
+impl ChannelInput {
+    /// Drains whatever is currently queued without blocking.
+    pub fn drain(&self) -> Vec<isize> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+impl ChannelOutput {
+    pub fn send(&self, value: isize) {
+        // A receiver that has gone away belongs to a machine that has stopped reading.
+        let _ = self.sender.send(value);
+    }
+}
+
+impl IntcodeInput for ChannelInput {
+    fn read(&mut self) -> Option<isize> {
+        if self.blocking {
+            self.receiver.recv().ok()
+        } else {
+            self.receiver.try_recv().ok()
+        }
+    }
+}
+
+impl IntcodeOutput for ChannelOutput {
+    fn write(&mut self, value: isize) {
+        self.send(value);
+    }
+}
+
+fn make_channel(blocking: bool) -> (ChannelOutput, ChannelInput) {
+    let (sender, receiver) = mpsc::channel();
+    (
+        ChannelOutput { sender },
+        ChannelInput { receiver, blocking },
+    )
+}
+
+/// Channel whose reading end blocks until a value arrives.
+pub fn blocking_channel() -> (ChannelOutput, ChannelInput) {
+    make_channel(true)
+}
+
+/// Channel whose reading end reports `BlockedOnInput` as soon as it runs dry.
+pub fn channel() -> (ChannelOutput, ChannelInput) {
+    make_channel(false)
+}
+
+pub type MachineThread<I, O, H> = JoinHandle<(IntcodeMachine<I, O, H>, IntcodeResult<StopStatus>)>;
+
+/// Runs the machine on its own thread. The machine is handed back when it stops, so values
+/// still queued on its ports aren't lost.
+pub fn spawn<I, O, H>(mut machine: IntcodeMachine<I, O, H>) -> MachineThread<I, O, H>
+where
+    I: IntcodeInput + Send + 'static,
+    O: IntcodeOutput + Send + 'static,
+    H: IntcodeHook + Send + 'static,
+{
+    thread::spawn(move || {
+        let result = machine.run();
+        (machine, result)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feedback_loop_across_threads() {
+        // `a` adds one to each value until it passes 100, `b` doubles values forever.
+        let a_tape = parse_intcode_program("3,20,1001,20,1,20,4,20,1007,20,100,21,1005,21,0,99");
+        let b_tape = parse_intcode_program("3,20,1002,20,2,20,4,20,1105,1,0");
+        let (to_a, a_input) = blocking_channel();
+        let (to_b, b_input) = blocking_channel();
+        let b = spawn(IntcodeMachine::new_io(b_tape, b_input, to_a.clone()));
+        let a = spawn(IntcodeMachine::new_io(a_tape, a_input, to_b));
+        to_a.send(1);
+
+        let (a, result) = a.join().unwrap();
+        assert_eq!(result, Ok(StopStatus::Halted));
+        assert_eq!(a.memory().get(20), 191);
+        // `b` waits for input for as long as `a` could still send it some.
+        assert!(!b.is_finished());
+        drop(a);
+        let (b, result) = b.join().unwrap();
+        assert_eq!(result, Ok(StopStatus::BlockedOnInput));
+        assert_eq!(b.pc(), 0);
+        assert_eq!(b.memory().get(20), 382);
+    }
+
+    #[test]
+    fn test_non_blocking_channel_reports_blocked_on_input() {
+        let (sender, input) = channel();
+        let (output, results) = channel();
+        let mut machine =
+            IntcodeMachine::new_io(parse_intcode_program("3,9,4,9,1105,1,0"), input, output);
+        assert_eq!(machine.run(), Ok(StopStatus::BlockedOnInput));
+        sender.send(4);
+        sender.send(2);
+        assert_eq!(machine.run(), Ok(StopStatus::BlockedOnInput));
+        assert_eq!(results.drain(), vec![4, 2]);
+    }
+}
@@ -0,0 +1,509 @@
+use super::*;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+/// Number of instructions the debugger can step back through by default, about 1MB of
+/// history. Change it with `Debugger::set_history_limit`.
+pub const DEFAULT_HISTORY: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DebugEvent {
+    /// Requested number of steps executed without anything noteworthy happening.
+    Stepped,
+    Breakpoint(isize),
+    Watchpoint {
+        addr: isize,
+        old: isize,
+        new: isize,
+    },
+    Stopped(StopStatus),
+    Fault(IntcodeError),
+    /// Stepped back to the instruction at `pc`, which changed `addr` from `old` to `new`.
+    LastWrite {
+        pc: isize,
+        addr: usize,
+        old: isize,
+        new: isize,
+    },
+    /// Stepped back as far as the recorded history goes.
+    HistoryStart,
+}
+
+/// What it takes to undo one completed instruction. An instruction stores at most one value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct UndoRecord {
+    pub pc: isize,
+    pub bp: isize,
+    /// Outputs counted towards the next `OutputReady` stop.
+    pub unyielded_outputs: usize,
+    /// Length of the output stream before the instruction.
+    pub output_len: usize,
+    /// Address written, with its values before and after.
+    pub write: Option<(usize, isize, isize)>,
+    pub input: Option<isize>,
+    pub output: Option<isize>,
+}
+
+/// Hook collecting an `UndoRecord` per instruction, keeping the most recent `capacity`.
+#[derive(Debug, Clone)]
+pub struct UndoLog {
+    records: VecDeque<UndoRecord>,
+    capacity: usize,
+    write: Option<(usize, isize, isize)>,
+    input: Option<isize>,
+    output: Option<isize>,
+}
+
+impl UndoLog {
+    pub fn new(capacity: usize) -> Self {
+        UndoLog {
+            records: VecDeque::new(),
+            capacity,
+            write: None,
+            input: None,
+            output: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.records.len() > capacity {
+            self.records.pop_front();
+        }
+    }
+
+    /// Files what the last instruction did under the state it started with.
+    fn commit(&mut self, start: UndoRecord) {
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        if self.capacity > 0 {
+            self.records.push_back(UndoRecord {
+                write: self.write,
+                input: self.input,
+                output: self.output,
+                ..start
+            });
+        }
+        self.discard();
+    }
+
+    fn discard(&mut self) {
+        self.write = None;
+        self.input = None;
+        self.output = None;
+    }
+}
+
+impl IntcodeHook for UndoLog {
+    fn on_write(&mut self, addr: usize, old: isize, new: isize) {
+        self.write = Some((addr, old, new));
+    }
+
+    fn on_input(&mut self, value: isize) {
+        self.input = Some(value);
+    }
+
+    fn on_output(&mut self, value: isize) {
+        self.output = Some(value);
+    }
+}
+
+#[derive(Debug)]
+pub struct Debugger {
+    pub machine: IntcodeMachine<StreamRef, StreamRef, UndoLog>,
+    breakpoints: BTreeSet<isize>,
+    watchpoints: BTreeMap<isize, isize>,
+}
+
+impl Debugger {
+    pub fn new(machine: IntcodeMachine) -> Self {
+        Debugger {
+            machine: machine.with_hook(UndoLog::new(DEFAULT_HISTORY)),
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+        }
+    }
+
+    pub fn peek(&self, addr: isize) -> Option<isize> {
+        if addr < 0 {
+            return None;
+        }
+        Some(self.machine.memory().get(addr as usize))
+    }
+
+    pub fn poke(&mut self, addr: isize, value: isize) -> IntcodeResult<()> {
+        self.machine.write_addr(addr, value)?;
+        if let Some(seen) = self.watchpoints.get_mut(&addr) {
+            *seen = value;
+        }
+        Ok(())
+    }
+
+    pub fn current_opcode(&self) -> IntcodeResult<Opcode> {
+        self.machine.decode_at(self.machine.pc() as usize)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = &isize> {
+        self.breakpoints.iter()
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = &isize> {
+        self.watchpoints.keys()
+    }
+
+    pub fn add_breakpoint(&mut self, pc: isize) -> bool {
+        self.breakpoints.insert(pc)
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: isize) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+
+    pub fn add_watchpoint(&mut self, addr: isize) -> bool {
+        match self.peek(addr) {
+            Some(value) => self.watchpoints.insert(addr, value).is_none(),
+            None => false,
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: isize) -> bool {
+        self.watchpoints.remove(&addr).is_some()
+    }
+
+    fn check_watchpoints(&mut self) -> Option<DebugEvent> {
+        let mut event = None;
+        for (addr, seen) in self.watchpoints.iter_mut() {
+            let current = self.machine.memory().get(*addr as usize);
+            if current != *seen && event.is_none() {
+                event = Some(DebugEvent::Watchpoint {
+                    addr: *addr,
+                    old: *seen,
+                    new: current,
+                });
+            }
+            *seen = current;
+        }
+        event
+    }
+
+    fn step_once(&mut self) -> Option<DebugEvent> {
+        let start = UndoRecord {
+            pc: self.machine.pc(),
+            bp: self.machine.bp(),
+            unyielded_outputs: self.machine.unyielded_outputs(),
+            output_len: self.machine.output.borrow().len(),
+            write: None,
+            input: None,
+            output: None,
+        };
+        let count = self.machine.instruction_count();
+        let result = self.machine.tick();
+        if self.machine.instruction_count() > count {
+            self.machine.hook.commit(start);
+        } else {
+            self.machine.hook.discard();
+        }
+        match result {
+            Ok(Some(status)) => Some(DebugEvent::Stopped(status)),
+            Ok(None) => self.check_watchpoints(),
+            Err(e) => Some(DebugEvent::Fault(e)),
+        }
+    }
+
+    /// Undoes the most recent recorded instruction, returning what it did. Writes made with
+    /// `poke` are not recorded. Taking back an output cuts the output stream back to its
+    /// length before the instruction, dropping anything added to it since.
+    fn unstep(&mut self) -> Option<UndoRecord> {
+        let record = self.machine.hook.records.pop_back()?;
+        if let Some((addr, old, _)) = record.write {
+            self.machine
+                .write_addr(addr as isize, old)
+                .expect("restoring a written address");
+        }
+        if let Some(value) = record.input {
+            self.machine.input.borrow_mut().push_front(value);
+        }
+        if record.output.is_some() {
+            self.machine.output.borrow_mut().truncate(record.output_len);
+        }
+        self.machine
+            .rewind(record.pc, record.bp, record.unyielded_outputs);
+        Some(record)
+    }
+
+    /// Number of instructions that can currently be stepped back through.
+    pub fn history_len(&self) -> usize {
+        self.machine.hook.len()
+    }
+
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.machine.hook.set_capacity(limit);
+    }
+
+    /// Undoes up to `count` instructions, stopping early when a watched address changes.
+    pub fn step_back(&mut self, count: usize) -> DebugEvent {
+        for _ in 0..count {
+            if self.unstep().is_none() {
+                return DebugEvent::HistoryStart;
+            }
+            if let Some(event) = self.check_watchpoints() {
+                return event;
+            }
+        }
+        DebugEvent::Stepped
+    }
+
+    /// Runs backwards until the pc reaches a breakpoint or a watched address changes.
+    pub fn reverse_cont(&mut self) -> DebugEvent {
+        loop {
+            if self.unstep().is_none() {
+                return DebugEvent::HistoryStart;
+            }
+            if let Some(event) = self.check_watchpoints() {
+                return event;
+            }
+            if self.breakpoints.contains(&self.machine.pc()) {
+                return DebugEvent::Breakpoint(self.machine.pc());
+            }
+        }
+    }
+
+    /// Runs backwards to just before the most recent instruction that wrote `addr`.
+    pub fn back_to_write(&mut self, addr: usize) -> DebugEvent {
+        loop {
+            let record = match self.unstep() {
+                Some(record) => record,
+                None => return DebugEvent::HistoryStart,
+            };
+            // Keep watchpoints in sync without stopping on them.
+            self.check_watchpoints();
+            match record.write {
+                Some((written, old, new)) if written == addr => {
+                    return DebugEvent::LastWrite {
+                        pc: record.pc,
+                        addr,
+                        old,
+                        new,
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Executes up to `count` instructions, stopping early on watchpoints and machine stops.
+    pub fn step(&mut self, count: usize) -> DebugEvent {
+        for _ in 0..count {
+            if let Some(event) = self.step_once() {
+                return event;
+            }
+        }
+        DebugEvent::Stepped
+    }
+
+    /// Runs until a breakpoint is reached, a watched address changes or the machine stops.
+    /// A breakpoint on the current pc does not trigger immediately.
+    pub fn cont(&mut self) -> DebugEvent {
+        loop {
+            if let Some(event) = self.step_once() {
+                return event;
+            }
+            if self.breakpoints.contains(&self.machine.pc()) {
+                return DebugEvent::Breakpoint(self.machine.pc());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Counts [20] up from 0 and outputs it until it reaches 3.
+    const COUNTER: &str = "1001,20,1,20,4,20,1007,20,3,21,1005,21,0,99";
+
+    fn debugger(program: &str) -> Debugger {
+        Debugger::new(IntcodeMachine::new(parse_intcode_program(program)))
+    }
+
+    fn outputs(dbg: &Debugger) -> Vec<isize> {
+        dbg.machine.output.borrow().iter().copied().collect()
+    }
+
+    #[test]
+    fn test_step() {
+        let mut dbg = debugger(COUNTER);
+        assert_eq!(dbg.step(2), DebugEvent::Stepped);
+        assert_eq!(dbg.machine.pc(), 6);
+        assert_eq!(outputs(&dbg), vec![1]);
+        assert_eq!(dbg.current_opcode().unwrap().operation, Operation::LessThan);
+    }
+
+    #[test]
+    fn test_breakpoint_is_not_hit_on_the_current_pc() {
+        let mut dbg = debugger(COUNTER);
+        assert!(dbg.add_breakpoint(0));
+        assert!(!dbg.add_breakpoint(0));
+        assert_eq!(dbg.cont(), DebugEvent::Breakpoint(0));
+        assert_eq!(outputs(&dbg), vec![1]);
+        assert_eq!(dbg.cont(), DebugEvent::Breakpoint(0));
+        assert_eq!(outputs(&dbg), vec![1, 2]);
+        assert!(dbg.remove_breakpoint(0));
+        assert_eq!(dbg.cont(), DebugEvent::Stopped(StopStatus::Halted));
+        assert_eq!(outputs(&dbg), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_watchpoint() {
+        let mut dbg = debugger(COUNTER);
+        assert!(dbg.add_watchpoint(21));
+        assert!(!dbg.add_watchpoint(-1));
+        assert_eq!(
+            dbg.cont(),
+            DebugEvent::Watchpoint {
+                addr: 21,
+                old: 0,
+                new: 1,
+            }
+        );
+        assert_eq!(dbg.machine.pc(), 10);
+
+        // Values set by hand do not trigger the watchpoint.
+        dbg.poke(21, 7).unwrap();
+        assert_eq!(dbg.peek(21), Some(7));
+        assert_eq!(
+            dbg.cont(),
+            DebugEvent::Watchpoint {
+                addr: 21,
+                old: 7,
+                new: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_step_back_undoes_everything() {
+        let mut dbg = debugger("3,30,1001,30,5,30,4,30,99");
+        dbg.machine.input.borrow_mut().push_back(2);
+        assert_eq!(dbg.step(3), DebugEvent::Stepped);
+        assert_eq!(outputs(&dbg), vec![7]);
+        assert_eq!(dbg.history_len(), 3);
+
+        assert_eq!(dbg.step_back(3), DebugEvent::Stepped);
+        assert_eq!(dbg.machine.pc(), 0);
+        assert_eq!(dbg.machine.instruction_count(), 0);
+        assert_eq!(dbg.peek(30), Some(0));
+        assert!(outputs(&dbg).is_empty());
+        assert_eq!(dbg.machine.input.borrow().front(), Some(&2));
+        assert_eq!(dbg.step_back(1), DebugEvent::HistoryStart);
+
+        assert_eq!(dbg.cont(), DebugEvent::Stopped(StopStatus::Halted));
+        assert_eq!(outputs(&dbg), vec![7]);
+    }
+
+    #[test]
+    fn test_step_back_restores_the_base_pointer() {
+        let mut dbg = debugger("109,5,109,-2,99");
+        assert_eq!(dbg.step(2), DebugEvent::Stepped);
+        assert_eq!(dbg.machine.bp(), 3);
+        dbg.step_back(1);
+        assert_eq!(dbg.machine.bp(), 5);
+    }
+
+    #[test]
+    fn test_step_back_truncates_output_to_its_earlier_length() {
+        let mut dbg = debugger("104,5,104,5,99");
+        assert_eq!(dbg.step(2), DebugEvent::Stepped);
+        dbg.machine.output.borrow_mut().push_back(9);
+        dbg.step_back(1);
+        assert_eq!(outputs(&dbg), vec![5]);
+
+        // Values drained by hand stay drained.
+        dbg.machine.output.borrow_mut().clear();
+        dbg.step_back(1);
+        assert!(outputs(&dbg).is_empty());
+    }
+
+    #[test]
+    fn test_step_back_restores_output_batches() {
+        let mut dbg = debugger("104,1,104,2,104,3,99");
+        dbg.machine.yield_on_output(Some(2));
+        assert_eq!(dbg.step(1), DebugEvent::Stepped);
+        dbg.step_back(1);
+        assert_eq!(dbg.step(1), DebugEvent::Stepped);
+        assert_eq!(dbg.step(1), DebugEvent::Stopped(StopStatus::OutputReady));
+        assert_eq!(outputs(&dbg), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_back_to_write() {
+        let mut dbg = debugger(COUNTER);
+        assert_eq!(dbg.cont(), DebugEvent::Stopped(StopStatus::Halted));
+        assert_eq!(
+            dbg.back_to_write(20),
+            DebugEvent::LastWrite {
+                pc: 0,
+                addr: 20,
+                old: 2,
+                new: 3,
+            }
+        );
+        assert_eq!(dbg.machine.pc(), 0);
+        assert_eq!(dbg.peek(20), Some(2));
+        assert_eq!(outputs(&dbg), vec![1, 2]);
+        assert_eq!(dbg.back_to_write(99), DebugEvent::HistoryStart);
+    }
+
+    #[test]
+    fn test_reverse_cont() {
+        let mut dbg = debugger(COUNTER);
+        assert_eq!(dbg.cont(), DebugEvent::Stopped(StopStatus::Halted));
+        dbg.add_breakpoint(4);
+        assert_eq!(dbg.reverse_cont(), DebugEvent::Breakpoint(4));
+        assert_eq!(outputs(&dbg), vec![1, 2]);
+        // [21] holds 1 from the first comparison on, so it last changed then.
+        dbg.remove_breakpoint(4);
+        assert!(dbg.add_watchpoint(21));
+        assert_eq!(
+            dbg.reverse_cont(),
+            DebugEvent::Watchpoint {
+                addr: 21,
+                old: 1,
+                new: 0,
+            }
+        );
+        assert_eq!(dbg.machine.pc(), 6);
+        assert_eq!(outputs(&dbg), vec![1]);
+    }
+
+    #[test]
+    fn test_history_limit() {
+        let mut dbg = debugger(COUNTER);
+        assert_eq!(dbg.machine.hook.capacity, DEFAULT_HISTORY);
+        dbg.set_history_limit(2);
+        assert_eq!(dbg.cont(), DebugEvent::Stopped(StopStatus::Halted));
+        assert_eq!(dbg.history_len(), 2);
+        assert_eq!(dbg.step_back(3), DebugEvent::HistoryStart);
+        assert_eq!(dbg.machine.pc(), 6);
+    }
+
+    #[test]
+    fn test_fault() {
+        let mut dbg = debugger("104,1,-5");
+        assert_eq!(dbg.step(1), DebugEvent::Stepped);
+        assert_eq!(
+            dbg.cont(),
+            DebugEvent::Fault(IntcodeError::NegativeOpcode { pc: 2, opcode: -5 })
+        );
+        assert_eq!(dbg.peek(-1), None);
+        assert!(dbg.poke(-1, 0).is_err());
+    }
+}
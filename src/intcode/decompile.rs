@@ -0,0 +1,450 @@
+//! Best-effort decompiler producing C-like pseudo-code. Functions are found through the usual
+//! calling convention: the caller stores a return address, then jumps unconditionally to the
+//! callee, which returns through a jump to a `bp`-relative address. Within a function,
+//! backward jumps become loops and forward jumps become `if`/`else` where the layout allows;
+//! anything else is left as a `goto`. `bp`-relative operands are named after their offset
+//! from the function's entry `bp`, e.g. `local_2`.
+
+use super::cfg::{build_cfg, BasicBlock, ControlFlowGraph};
+use super::disasm::{is_never_taken, is_unconditional_transfer, jump_target, stored_constant};
+use super::*;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+const INDENT: &str = "    ";
+
+/// Where the calls are and which stores merely push their return addresses.
+struct CallSites {
+    /// Jump instruction address to callee entry.
+    calls: BTreeMap<usize, usize>,
+    return_pushes: BTreeSet<usize>,
+}
+
+fn find_call_sites(cfg: &ControlFlowGraph) -> CallSites {
+    let mut calls = BTreeMap::new();
+    let mut return_pushes = BTreeSet::new();
+    for block in cfg.blocks.values() {
+        let (jump, opcode) = block.instructions.last().unwrap();
+        let target = match jump_target(opcode) {
+            Some(target) if is_unconditional_transfer(opcode) => target,
+            _ => continue,
+        };
+        let push = block
+            .instructions
+            .iter()
+            .rev()
+            .skip(1)
+            .find(|(_, op)| stored_constant(op) == Some(block.end as isize));
+        if let Some((push, _)) = push {
+            calls.insert(*jump, target);
+            return_pushes.insert(*push);
+        }
+    }
+    CallSites {
+        calls,
+        return_pushes,
+    }
+}
+
+fn function_name(entry: usize) -> String {
+    if entry == 0 {
+        "main".to_string()
+    } else {
+        format!("f_{:04}", entry)
+    }
+}
+
+fn local_name(offset: isize) -> String {
+    if offset < 0 {
+        format!("local_m{}", offset.unsigned_abs())
+    } else {
+        format!("local_{}", offset)
+    }
+}
+
+/// Blocks reachable from `entry` without descending into callees; a call continues at its
+/// return address instead.
+fn function_blocks<'a>(
+    cfg: &'a ControlFlowGraph,
+    sites: &CallSites,
+    entry: usize,
+    claimed: &mut BTreeSet<usize>,
+) -> Vec<&'a BasicBlock> {
+    let mut blocks = Vec::new();
+    let mut queue = VecDeque::from(vec![entry]);
+    while let Some(start) = queue.pop_front() {
+        let block = match cfg.blocks.get(&start) {
+            Some(block) if claimed.insert(start) => block,
+            _ => continue,
+        };
+        let (last, _) = block.instructions.last().unwrap();
+        if sites.calls.contains_key(last) {
+            queue.push_back(block.end);
+        } else {
+            queue.extend(block.successors.iter().map(|(target, _)| *target));
+        }
+        blocks.push(block);
+    }
+    blocks.sort_by_key(|block| block.start);
+    blocks
+}
+
+struct FunctionWriter<'a> {
+    sites: &'a CallSites,
+    code: Vec<(usize, Opcode)>,
+    /// `bp` relative to its value on entry, while it can be tracked.
+    bp: Option<isize>,
+    lines: Vec<(Option<usize>, String)>,
+    gotos: BTreeSet<usize>,
+}
+
+impl<'a> FunctionWriter<'a> {
+    fn operand(&self, operand: &Operand) -> String {
+        match operand.mode {
+            AddressingMode::Immediate => operand.value.to_string(),
+            AddressingMode::AbsoluteAddress => format!("mem[{}]", operand.value),
+            AddressingMode::BasePointerRelative => {
+                match self.bp.and_then(|bp| bp.checked_add(operand.value)) {
+                    Some(offset) => local_name(offset),
+                    None => format!("bp[{}]", operand.value),
+                }
+            }
+        }
+    }
+
+    fn condition(&self, opcode: &Opcode, negate: bool) -> String {
+        let value = self.operand(&opcode.operands[0]);
+        let jumps_if_zero = opcode.operation == Operation::JumpFalse;
+        if jumps_if_zero != negate {
+            format!("!{}", value)
+        } else {
+            value
+        }
+    }
+
+    fn push(&mut self, indent: usize, text: String) {
+        self.lines
+            .push((None, format!("{}{}", INDENT.repeat(indent), text)));
+    }
+
+    fn index_of(&self, address: usize, from: usize, to: usize) -> Option<usize> {
+        (from..to).find(|&i| self.code[i].0 == address)
+    }
+
+    fn is_plain_jump(&self, index: usize) -> bool {
+        let (address, opcode) = &self.code[index];
+        matches!(opcode.operation, Operation::JumpTrue | Operation::JumpFalse)
+            && !self.sites.calls.contains_key(address)
+            && !is_never_taken(opcode)
+            && jump_target(opcode).is_some()
+    }
+
+    fn write_range(&mut self, from: usize, to: usize, indent: usize) {
+        let mut i = from;
+        while i < to {
+            let (address, opcode) = self.code[i];
+
+            // The last jump back to this instruction closes a loop around everything between.
+            let back_jump = (i..to)
+                .rev()
+                .find(|&j| self.is_plain_jump(j) && jump_target(&self.code[j].1) == Some(address));
+            if let Some(j) = back_jump {
+                let jump = self.code[j].1;
+                if is_unconditional_transfer(&jump) {
+                    self.push(indent, "while (1) {".to_string());
+                    self.write_range(i, j, indent + 1);
+                    self.push(indent, "}".to_string());
+                } else {
+                    self.push(indent, "do {".to_string());
+                    self.write_range(i, j, indent + 1);
+                    let condition = self.condition(&jump, false);
+                    self.push(indent, format!("}} while ({});", condition));
+                }
+                i = j + 1;
+                continue;
+            }
+            self.lines.push((Some(address), String::new()));
+
+            if self.is_plain_jump(i) && !is_unconditional_transfer(&opcode) {
+                // Jumping to the instruction right after the range is fine too.
+                let limit = (to + 1).min(self.code.len());
+                let target = jump_target(&opcode).unwrap();
+                if let Some(k) = self.index_of(target, i + 1, limit) {
+                    let condition = self.condition(&opcode, true);
+                    // A forward jump at the end of the `then` part skipping past more code
+                    // makes it an if/else.
+                    let skip = if k > i + 1 && self.is_plain_jump(k - 1) {
+                        let jump = self.code[k - 1].1;
+                        jump_target(&jump)
+                            .filter(|_| is_unconditional_transfer(&jump))
+                            .and_then(|end| self.index_of(end, k + 1, limit))
+                    } else {
+                        None
+                    };
+
+                    self.push(indent, format!("if ({}) {{", condition));
+                    match skip {
+                        Some(m) => {
+                            self.write_range(i + 1, k - 1, indent + 1);
+                            self.push(indent, "} else {".to_string());
+                            self.write_range(k, m, indent + 1);
+                            i = m;
+                        }
+                        None => {
+                            self.write_range(i + 1, k, indent + 1);
+                            i = k;
+                        }
+                    }
+                    self.push(indent, "}".to_string());
+                    continue;
+                }
+            }
+
+            if let Some(text) = self.statement(address, &opcode) {
+                self.push(indent, text);
+            }
+            i += 1;
+        }
+    }
+
+    fn statement(&mut self, address: usize, opcode: &Opcode) -> Option<String> {
+        let ops = &opcode.operands;
+        let text = match opcode.operation {
+            Operation::Add | Operation::Multiply if self.sites.return_pushes.contains(&address) => {
+                return None
+            }
+            Operation::Add => {
+                let (a, b) = (self.operand(&ops[0]), self.operand(&ops[1]));
+                let value = match (ops[0].mode, ops[0].value, ops[1].mode, ops[1].value) {
+                    (_, _, AddressingMode::Immediate, 0) => a,
+                    (AddressingMode::Immediate, 0, _, _) => b,
+                    (_, _, AddressingMode::Immediate, v) if v < 0 => {
+                        format!("{} - {}", a, v.unsigned_abs())
+                    }
+                    _ => format!("{} + {}", a, b),
+                };
+                format!("{} = {};", self.operand(&ops[2]), value)
+            }
+            Operation::Multiply => {
+                let (a, b) = (self.operand(&ops[0]), self.operand(&ops[1]));
+                let value = match (ops[1].mode, ops[1].value) {
+                    (AddressingMode::Immediate, 1) => a,
+                    (AddressingMode::Immediate, -1) => format!("-{}", a),
+                    _ => format!("{} * {}", a, b),
+                };
+                format!("{} = {};", self.operand(&ops[2]), value)
+            }
+            Operation::Input => format!("{} = input();", self.operand(&ops[0])),
+            Operation::Output => format!("output({});", self.operand(&ops[0])),
+            Operation::LessThan | Operation::Equals => {
+                let relation = if opcode.operation == Operation::LessThan {
+                    "<"
+                } else {
+                    "=="
+                };
+                format!(
+                    "{} = {} {} {};",
+                    self.operand(&ops[2]),
+                    self.operand(&ops[0]),
+                    relation,
+                    self.operand(&ops[1])
+                )
+            }
+            Operation::AdjustBasePointer => {
+                let text = match ops[0].mode {
+                    AddressingMode::Immediate if ops[0].value < 0 => {
+                        format!("bp -= {};", ops[0].value.unsigned_abs())
+                    }
+                    _ => format!("bp += {};", self.operand(&ops[0])),
+                };
+                self.bp = match (self.bp, ops[0].mode) {
+                    // Past the ends of the address space bp is no longer worth tracking.
+                    (Some(bp), AddressingMode::Immediate) => bp.checked_add(ops[0].value),
+                    _ => None,
+                };
+                text
+            }
+            Operation::Halt => "halt();".to_string(),
+            Operation::JumpTrue | Operation::JumpFalse => {
+                if let Some(callee) = self.sites.calls.get(&address) {
+                    return Some(format!("{}();", function_name(*callee)));
+                }
+                if is_never_taken(opcode) {
+                    return None;
+                }
+                let goto = match jump_target(opcode) {
+                    Some(target) => {
+                        self.gotos.insert(target);
+                        format!("goto {};", disasm::label_name(target))
+                    }
+                    None if ops[1].mode == AddressingMode::BasePointerRelative => {
+                        "return;".to_string()
+                    }
+                    None => format!("goto *{};", self.operand(&ops[1])),
+                };
+                if is_unconditional_transfer(opcode) {
+                    goto
+                } else {
+                    format!("if ({}) {}", self.condition(opcode, false), goto)
+                }
+            }
+            Operation::Extended(_) => format!("{};", opcode),
+        };
+        Some(text)
+    }
+
+    /// Lines of the body, with labels kept only where a `goto` needs them.
+    fn finish(self) -> Vec<String> {
+        let gotos = self.gotos;
+        self.lines
+            .into_iter()
+            .filter_map(|(label, text)| match label {
+                Some(address) if gotos.contains(&address) => {
+                    Some(format!("{}:", disasm::label_name(address)))
+                }
+                Some(_) => None,
+                None => Some(text),
+            })
+            .collect()
+    }
+}
+
+/// Decompiles the code reachable from pc 0 into one pseudo-C function per discovered entry.
+pub fn decompile(tape: &[isize]) -> String {
+    let cfg = build_cfg(tape);
+    let sites = find_call_sites(&cfg);
+    let entries: BTreeSet<usize> = std::iter::once(0)
+        .chain(sites.calls.values().copied())
+        .collect();
+
+    let patched: BTreeSet<usize> = cfg
+        .self_modifying_writes
+        .iter()
+        .map(|write| write.instruction)
+        .collect();
+
+    let mut claimed = BTreeSet::new();
+    let mut out = String::new();
+    for entry in entries {
+        let blocks = function_blocks(&cfg, &sites, entry, &mut claimed);
+        if blocks.is_empty() {
+            continue;
+        }
+
+        let mut writer = FunctionWriter {
+            sites: &sites,
+            code: blocks
+                .iter()
+                .flat_map(|block| block.instructions.iter().copied())
+                .collect(),
+            bp: Some(0),
+            lines: Vec::new(),
+            gotos: BTreeSet::new(),
+        };
+        let count = writer.code.len();
+        writer.write_range(0, count, 1);
+
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&format!("void {}() {{\n", function_name(entry)));
+        let patched_here: Vec<String> = writer
+            .code
+            .iter()
+            .filter(|(address, _)| patched.contains(address))
+            .map(|(address, _)| address.to_string())
+            .collect();
+        if !patched_here.is_empty() {
+            out.push_str(&format!(
+                "{}// modified at run time: instructions at {}\n",
+                INDENT,
+                patched_here.join(", ")
+            ));
+        }
+        for line in writer.finish() {
+            out.push_str(&line);
+            out.push('\n');
+        }
+        out.push_str("}\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loop() {
+        let tape = parse_intcode_program("1001,20,1,20,4,20,1007,20,3,21,1005,21,0,99");
+        assert_eq!(
+            decompile(&tape),
+            "void main() {
+    do {
+        mem[20] = mem[20] + 1;
+        output(mem[20]);
+        mem[21] = mem[20] < 3;
+    } while (mem[21]);
+    halt();
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_if_else() {
+        let tape = parse_intcode_program("3,20,1005,20,10,104,0,1105,1,12,104,1,99");
+        assert_eq!(
+            decompile(&tape),
+            "void main() {
+    mem[20] = input();
+    if (!mem[20]) {
+        output(0);
+    } else {
+        output(1);
+    }
+    halt();
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_call_and_return() {
+        // Pushes the return address 7 to [bp+0], calls 8, which returns through [bp+0].
+        let tape = parse_intcode_program("21101,7,0,0,1105,1,8,99,104,7,2105,1,0");
+        assert_eq!(
+            decompile(&tape),
+            "void main() {
+    f_0008();
+    halt();
+}
+
+void f_0008() {
+    output(7);
+    return;
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_extreme_base_pointer_offsets() {
+        let body = |tape: &[isize]| {
+            let text = decompile(tape);
+            text.lines().nth(1).unwrap().trim().to_string()
+        };
+        assert_eq!(body(&[109, isize::MIN, 99]), "bp -= 9223372036854775808;");
+        assert_eq!(
+            body(&[204, isize::MIN, 99]),
+            "output(local_m9223372036854775808);"
+        );
+        let text = decompile(&[109, isize::MAX, 204, 1, 99]);
+        assert!(text.contains("output(bp[1]);"), "{}", text);
+        let text = decompile(&[1101, 5, isize::MIN, 0, 99]);
+        assert!(
+            text.contains("mem[0] = 5 - 9223372036854775808;"),
+            "{}",
+            text
+        );
+    }
+}
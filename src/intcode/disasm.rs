@@ -0,0 +1,317 @@
+use super::*;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+const DATA_WORDS_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineKind {
+    Code(Opcode),
+    Data,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub address: usize,
+    pub words: Vec<isize>,
+    pub kind: LineKind,
+}
+
+#[derive(Debug, Clone)]
+pub struct Listing {
+    pub lines: Vec<Line>,
+    pub labels: BTreeSet<usize>,
+}
+
+pub fn label_name(address: usize) -> String {
+    format!("L{:04}", address)
+}
+
+/// Decodes the instruction at `address` without touching anything past the end of the tape
+/// (reads beyond it yield zero, just like an `IntcodeMachine` would see).
+pub fn decode_at(tape: &[isize], address: usize) -> IntcodeResult<Opcode> {
+    let mut cursor = address;
+    decode_opcode(address as isize, || {
+        let value = tape.get(cursor).copied().unwrap_or(0);
+        cursor += 1;
+        Ok(value)
+    })
+}
+
+fn immediate(operand: &Operand) -> Option<isize> {
+    match operand.mode {
+        AddressingMode::Immediate => Some(operand.value),
+        _ => None,
+    }
+}
+
+/// Whether control can never continue to the next instruction.
+pub fn is_unconditional_transfer(opcode: &Opcode) -> bool {
+    match opcode.operation {
+        Operation::Halt => true,
+        Operation::JumpTrue => immediate(&opcode.operands[0]).is_some_and(|c| c != 0),
+        Operation::JumpFalse => immediate(&opcode.operands[0]) == Some(0),
+        _ => false,
+    }
+}
+
+/// The statically known destination of a jump instruction, if it has an immediate target.
+pub fn jump_target(opcode: &Opcode) -> Option<usize> {
+    match opcode.operation {
+        Operation::JumpTrue | Operation::JumpFalse => {
+            let target = immediate(&opcode.operands[1])?;
+            if target >= 0 {
+                Some(target as usize)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Whether the jump can never be taken, i.e. it always falls through.
+pub fn is_never_taken(opcode: &Opcode) -> bool {
+    match opcode.operation {
+        Operation::JumpTrue => immediate(&opcode.operands[0]) == Some(0),
+        Operation::JumpFalse => immediate(&opcode.operands[0]).is_some_and(|c| c != 0),
+        _ => false,
+    }
+}
+
+/// Constant stored verbatim into memory (`ADD #x, #0, dst` and friends), the usual way a
+/// return address gets pushed before a call.
+pub fn stored_constant(opcode: &Opcode) -> Option<isize> {
+    let a = immediate(opcode.operands.first()?);
+    let b = immediate(opcode.operands.get(1)?);
+    match (opcode.operation, a, b) {
+        (Operation::Add, Some(x), Some(0)) | (Operation::Add, Some(0), Some(x)) => Some(x),
+        (Operation::Multiply, Some(x), Some(1)) | (Operation::Multiply, Some(1), Some(x)) => {
+            Some(x)
+        }
+        _ => None,
+    }
+}
+
+/// Recursive traversal from pc 0 following fall-through and immediate jump targets. Stored
+/// constants that point just past an unconditional jump are taken to be return addresses.
+/// Returns the decoded instructions by address, plus the jump targets.
+pub fn trace_code(tape: &[isize]) -> (Vec<(usize, Opcode)>, BTreeSet<usize>) {
+    let mut decoded = BTreeMap::<usize, Opcode>::new();
+    let mut covered = HashSet::<usize>::new();
+    let mut targets = BTreeSet::<usize>::new();
+    let mut return_candidates = BTreeSet::<usize>::new();
+    let mut worklist = vec![0usize];
+
+    loop {
+        while let Some(mut address) = worklist.pop() {
+            while address < tape.len() && !decoded.contains_key(&address) {
+                if covered.contains(&address) {
+                    break;
+                }
+                let opcode = match decode_at(tape, address) {
+                    Ok(opcode) => opcode,
+                    Err(_) => break,
+                };
+                let end = address + opcode.size();
+                if end > tape.len() || (address + 1..end).any(|a| decoded.contains_key(&a)) {
+                    break;
+                }
+
+                if let Some(target) = jump_target(&opcode) {
+                    if !is_never_taken(&opcode) && target < tape.len() {
+                        targets.insert(target);
+                        worklist.push(target);
+                    }
+                }
+                if let Some(constant) = stored_constant(&opcode) {
+                    if constant >= 0 {
+                        return_candidates.insert(constant as usize);
+                    }
+                }
+
+                let stop = is_unconditional_transfer(&opcode);
+                covered.extend(address..end);
+                decoded.insert(address, opcode);
+                if stop {
+                    break;
+                }
+                address = end;
+            }
+        }
+
+        let follows_jump = |address: &usize| {
+            decoded
+                .range(..*address)
+                .next_back()
+                .is_some_and(|(start, opcode)| {
+                    start + opcode.size() == *address
+                        && opcode.operation != Operation::Halt
+                        && is_unconditional_transfer(opcode)
+                })
+        };
+        worklist.extend(
+            return_candidates
+                .iter()
+                .filter(|a| !covered.contains(a) && follows_jump(a)),
+        );
+        if worklist.is_empty() {
+            break;
+        }
+        targets.extend(worklist.iter().copied());
+    }
+
+    (decoded.into_iter().collect(), targets)
+}
+
+pub fn disassemble(tape: &Tape) -> Listing {
+    let (code, mut labels) = trace_code(tape);
+
+    let mut lines = Vec::<Line>::new();
+    let mut address = 0usize;
+    let flush_data = |lines: &mut Vec<Line>, from: usize, to: usize| {
+        let mut start = from;
+        while start < to {
+            let end = (start + DATA_WORDS_PER_LINE).min(to);
+            // Keep labelled words at the start of a line so the label is visible.
+            let end = (start + 1..end).find(|a| labels.contains(a)).unwrap_or(end);
+            lines.push(Line {
+                address: start,
+                words: tape[start..end].to_vec(),
+                kind: LineKind::Data,
+            });
+            start = end;
+        }
+    };
+
+    for (start, opcode) in code {
+        flush_data(&mut lines, address, start);
+        address = start + opcode.size();
+        lines.push(Line {
+            address: start,
+            words: tape[start..address].to_vec(),
+            kind: LineKind::Code(opcode),
+        });
+    }
+    flush_data(&mut lines, address, tape.len());
+
+    // A jump into the middle of an instruction has no line to hang its label on; keep the
+    // raw address in the operand rather than referencing a label that is never printed.
+    let starts: HashSet<usize> = lines.iter().map(|line| line.address).collect();
+    labels.retain(|address| starts.contains(address));
+
+    Listing { lines, labels }
+}
+
+fn format_words(words: &[isize], sep: &str) -> String {
+    words
+        .iter()
+        .map(|w| w.to_string())
+        .collect::<Vec<_>>()
+        .join(sep)
+}
+
+impl Listing {
+    /// Renders the mnemonic part of a line, replacing known jump targets with their labels.
+    pub fn format_body(&self, line: &Line) -> String {
+        let opcode = match &line.kind {
+            LineKind::Code(opcode) => opcode,
+            LineKind::Data => return format!(".data {}", format_words(&line.words, ", ")),
+        };
+
+        let mut text = opcode.operation.mnemonic().to_string();
+        for (i, operand) in opcode.operands.iter().enumerate() {
+            text.push_str(if i == 0 { " " } else { ", " });
+            match jump_target(opcode) {
+                Some(target) if i == 1 && self.labels.contains(&target) => {
+                    text.push_str(&format!("#{}", label_name(target)))
+                }
+                _ => text.push_str(&operand.to_string()),
+            }
+        }
+        text
+    }
+
+    /// Renders the listing with `margin` called for every line and its result placed in a
+    /// column to the left of the address, e.g. to overlay profiling data.
+    pub fn render_with(&self, margin: impl Fn(&Line) -> String) -> String {
+        let mut out = String::new();
+        for line in &self.lines {
+            let margin = margin(line);
+            let pad = if margin.is_empty() { "" } else { "  " };
+            if self.labels.contains(&line.address) {
+                out.push_str(&format!(
+                    "{:width$}{}{}:\n",
+                    "",
+                    pad,
+                    label_name(line.address),
+                    width = margin.len()
+                ));
+            }
+            let raw = match line.kind {
+                LineKind::Code(_) => format_words(&line.words, ","),
+                LineKind::Data => String::new(),
+            };
+            let text = format!(
+                "{}{}{:>6}  {:<24} {}",
+                margin,
+                pad,
+                line.address,
+                raw,
+                self.format_body(line)
+            );
+            out.push_str(text.trim_end());
+            out.push('\n');
+        }
+        out
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.render_with(|_| String::new()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_labels_jump_targets() {
+        // JF #0, #L0006; OUT #1; HLT; OUT #2; HLT
+        let listing = disassemble(&vec![1106, 0, 6, 104, 1, 99, 104, 2, 99]);
+        assert_eq!(listing.labels, [6].iter().copied().collect());
+        let text = listing.to_string();
+        assert!(text.contains("JF #0, #L0006"), "{}", text);
+        assert!(text.contains("L0006:\n     6  104,2"), "{}", text);
+    }
+
+    #[test]
+    fn test_unreached_words_are_data() {
+        let listing = disassemble(&vec![104, 7, 99, 5, 6, 7]);
+        let kinds: Vec<_> = listing
+            .lines
+            .iter()
+            .map(|line| (line.address, line.kind == LineKind::Data))
+            .collect();
+        assert_eq!(kinds, vec![(0, false), (2, false), (3, true)]);
+        assert!(listing.to_string().ends_with(".data 5, 6, 7\n"));
+    }
+
+    #[test]
+    fn test_no_label_inside_an_instruction() {
+        // JT [10], #4 lands on the second word of the ADD at 3, which only exists as an
+        // operand, so there is no line to label.
+        let listing = disassemble(&vec![1005, 10, 4, 1101, 99, 0, 10, 99, 0, 0, 0]);
+        assert!(listing.labels.is_empty());
+        let text = listing.to_string();
+        assert!(text.contains("JT [10], #4"), "{}", text);
+        assert!(!text.contains("L0004"), "{}", text);
+    }
+
+    #[test]
+    fn test_most_negative_relative_operand() {
+        let text = disassemble(&vec![204, isize::MIN, 99]).to_string();
+        assert!(text.contains("OUT [bp-9223372036854775808]"), "{}", text);
+    }
+}
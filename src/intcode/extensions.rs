@@ -0,0 +1,241 @@
+//! Custom opcodes. Register them on an `ExtensionRegistry` and hand it to a machine with
+//! `IntcodeMachine::set_extensions`; the machine then decodes and executes them alongside
+//! the built-in instruction set.
+
+use super::memory::Memory;
+use super::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Everything a handler gets to see when its opcode executes.
+#[derive(Debug)]
+pub struct ExtensionCall<'a, W = isize> {
+    pub pc: isize,
+    pub bp: isize,
+    /// Values of the operands that are read, i.e. all but the destination if there is one.
+    pub args: &'a [W],
+    pub memory: &'a Memory<W>,
+}
+
+/// What the machine does once a handler returns.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExtensionAction<W = isize> {
+    Continue,
+    /// Writes the value to the destination operand and continues.
+    Store(W),
+    Jump(W),
+    Halt,
+}
+
+/// A handler's `Err` becomes `IntcodeError::ExtensionFailed` carrying its message.
+pub type ExtensionHandler<W = isize> =
+    Arc<dyn Fn(&ExtensionCall<W>) -> Result<ExtensionAction<W>, String> + Send + Sync>;
+
+/// Handlers for machines with `W` words.
+#[derive(Clone)]
+pub struct ExtensionRegistry<W = isize> {
+    handlers: HashMap<usize, (ExtendedOperation, ExtensionHandler<W>)>,
+}
+
+impl ExtensionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<W> Default for ExtensionRegistry<W> {
+    fn default() -> Self {
+        ExtensionRegistry {
+            handlers: HashMap::new(),
+        }
+    }
+}
+
+impl<W> ExtensionRegistry<W> {
+    /// Adds opcode `code`, replacing any earlier registration of it. If `stores_result` is
+    /// set the last of `operand_count` operands is the destination for
+    /// `ExtensionAction::Store`.
+    ///
+    /// Panics if `code` isn't a two-digit operation code or belongs to a built-in operation,
+    /// or if the operand layout isn't one the opcode word can describe.
+    pub fn register<F>(
+        &mut self,
+        code: usize,
+        mnemonic: &'static str,
+        operand_count: usize,
+        stores_result: bool,
+        handler: F,
+    ) -> &mut Self
+    where
+        F: Fn(&ExtensionCall<W>) -> Result<ExtensionAction<W>, String> + Send + Sync + 'static,
+    {
+        assert!(
+            (1..100).contains(&code),
+            "operation code {} is not two digits",
+            code
+        );
+        assert!(
+            Operation::from_code(code).is_none(),
+            "operation code {} is built in",
+            code
+        );
+        assert!(
+            operand_count <= MAX_OPERANDS,
+            "at most {} operands are supported",
+            MAX_OPERANDS
+        );
+        assert!(
+            !stores_result || operand_count > 0,
+            "a result needs a destination operand"
+        );
+
+        let operation = ExtendedOperation {
+            code: code as u8,
+            mnemonic,
+            operand_count: operand_count as u8,
+            stores_result,
+        };
+        self.handlers.insert(code, (operation, Arc::new(handler)));
+        self
+    }
+
+    pub fn operation(&self, code: usize) -> Option<Operation> {
+        self.handlers
+            .get(&code)
+            .map(|(operation, _)| Operation::Extended(*operation))
+    }
+
+    pub fn operations(&self) -> impl Iterator<Item = Operation> + '_ {
+        self.handlers
+            .values()
+            .map(|(operation, _)| Operation::Extended(*operation))
+    }
+
+    pub(crate) fn handler(&self, code: usize) -> Option<&ExtensionHandler<W>> {
+        self.handlers.get(&code).map(|(_, handler)| handler)
+    }
+}
+
+impl<W> fmt::Debug for ExtensionRegistry<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.operations()).finish()
+    }
+}
+
+/// Registry with a couple of generally useful extensions:
+///
+/// * `DBG a` (50) prints `a` to stderr.
+/// * `ASSERT a, b` (51) fails unless `a == b`.
+pub fn standard_extensions() -> ExtensionRegistry {
+    let mut registry = ExtensionRegistry::new();
+    registry
+        .register(50, "DBG", 1, false, |call| {
+            eprintln!("[pc {}] {}", call.pc, call.args[0]);
+            Ok(ExtensionAction::Continue)
+        })
+        .register(51, "ASSERT", 2, false, |call| {
+            if call.args[0] == call.args[1] {
+                Ok(ExtensionAction::Continue)
+            } else {
+                Err(format!(
+                    "assertion failed: {} != {}",
+                    call.args[0], call.args[1]
+                ))
+            }
+        });
+    registry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> ExtensionRegistry {
+        let mut registry = ExtensionRegistry::new();
+        registry
+            .register(60, "MAX", 3, true, |call| {
+                Ok(ExtensionAction::Store(call.args[0].max(call.args[1])))
+            })
+            .register(61, "JMP", 1, false, |call| {
+                Ok(ExtensionAction::Jump(call.args[0]))
+            })
+            .register(62, "STOP", 0, false, |_| Ok(ExtensionAction::Halt))
+            .register(63, "BAD", 1, false, |_| Ok(ExtensionAction::Store(1)))
+            .register(64, "PEEK", 2, true, |call| {
+                let addr = call.bp + call.args[0];
+                Ok(ExtensionAction::Store(call.memory.get(addr as usize)))
+            });
+        registry
+    }
+
+    fn machine(program: &str) -> IntcodeMachine {
+        let mut machine = IntcodeMachine::new(parse_intcode_program(program));
+        machine.set_extensions(Some(Arc::new(registry())));
+        machine
+    }
+
+    fn outputs(machine: &IntcodeMachine) -> Vec<isize> {
+        machine.output.borrow().iter().copied().collect()
+    }
+
+    #[test]
+    fn test_store_jump_and_halt() {
+        // MAX #3, #9, [11]; JMP #7; OUT #1; OUT [11]; STOP
+        let mut machine = machine("1160,3,9,11,161,7,104,4,11,62,0,0");
+        assert_eq!(machine.run(), Ok(StopStatus::Halted));
+        assert_eq!(machine.pc(), 9);
+        assert_eq!(outputs(&machine), vec![9]);
+    }
+
+    #[test]
+    fn test_handler_sees_registers_and_memory() {
+        // ARB #10; PEEK #2, [9] reads [bp+2]; OUT [9]; HLT; .data 0, 0, 0, 0, 42
+        let mut machine = machine("109,10,164,2,9,4,9,99,0,0,0,0,42");
+        assert_eq!(machine.run(), Ok(StopStatus::Halted));
+        assert_eq!(outputs(&machine), vec![42]);
+    }
+
+    #[test]
+    fn test_decodes_with_mnemonic() {
+        let machine = machine("1160,3,9,11");
+        assert_eq!(
+            machine.decode_at(0).unwrap().to_string(),
+            "MAX #3, #9, [11]"
+        );
+    }
+
+    #[test]
+    fn test_failures() {
+        assert_eq!(
+            machine("63,0").run(),
+            Err(IntcodeError::ExtensionFailed {
+                pc: 0,
+                opcode: 63,
+                message: "BAD has no destination operand".to_string(),
+            })
+        );
+
+        let mut unregistered = IntcodeMachine::new(parse_intcode_program("62"));
+        assert_eq!(
+            unregistered.run(),
+            Err(IntcodeError::InvalidOpcodeOperation { pc: 0, opcode: 62 })
+        );
+    }
+
+    #[test]
+    fn test_re_registering_replaces() {
+        let mut registry = registry();
+        registry.register(62, "NOP", 0, false, |_| Ok(ExtensionAction::Continue));
+        assert_eq!(registry.operations().count(), 5);
+        match registry.operation(62) {
+            Some(Operation::Extended(op)) => assert_eq!(op.mnemonic, "NOP"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "operation code 1 is built in")]
+    fn test_cannot_replace_built_ins() {
+        ExtensionRegistry::new().register(1, "ADD2", 3, true, |_| Ok(ExtensionAction::Continue));
+    }
+}
@@ -0,0 +1,317 @@
+//! Reading and writing tapes. The text format is the puzzle's comma-separated list, relaxed
+//! to also allow whitespace or newlines between elements and comments from `;` or `#` to the
+//! end of a line. The compact format is a binary encoding for caching large programs: a
+//! magic header, the element count, then each element as a zigzag LEB128 varint.
+
+use super::*;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/// Start of every compact tape. The leading NUL never appears in a text program.
+pub const COMPACT_MAGIC: &[u8] = b"\0ICT1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    InvalidNumber {
+        line: usize,
+        text: String,
+    },
+    /// A comma with no element before or after it.
+    EmptyElement {
+        line: usize,
+    },
+    /// The bytes don't start with `COMPACT_MAGIC`.
+    NotCompact,
+    /// A compact tape ended in the middle of an element or before all elements.
+    Truncated,
+    /// A compact tape element doesn't fit in an `isize`.
+    Overflow,
+    /// A compact tape has bytes left over after its last element.
+    TrailingBytes,
+}
+
+/// `index` is the zero-based position of the offending element in the tape.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub index: usize,
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "element {}: ", self.index)?;
+        match &self.kind {
+            ParseErrorKind::InvalidNumber { line, text } => {
+                write!(f, "invalid number '{}' on line {}", text, line)
+            }
+            ParseErrorKind::EmptyElement { line } => write!(f, "empty element on line {}", line),
+            ParseErrorKind::NotCompact => write!(f, "not a compact tape"),
+            ParseErrorKind::Truncated => write!(f, "compact tape is truncated"),
+            ParseErrorKind::Overflow => write!(f, "compact tape value out of range"),
+            ParseErrorKind::TrailingBytes => write!(f, "compact tape has trailing bytes"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub fn try_parse_intcode_program(input: &str) -> Result<Tape, ParseError> {
+    parse_words(input)
+}
+
+/// The text format for any word type, so wider tapes share the same syntax.
+pub(crate) fn parse_words<W: FromStr>(input: &str) -> Result<Vec<W>, ParseError> {
+    let mut tape = Vec::new();
+    // Commas are optional, but a comma must sit between two elements.
+    let mut after_element = false;
+    let mut open_comma = None;
+    for (number, line) in input.lines().enumerate() {
+        let line_number = number + 1;
+        let code = line.split(&[';', '#'][..]).next().unwrap();
+        for (i, part) in code.split(',').enumerate() {
+            if i > 0 {
+                if !after_element {
+                    return Err(ParseError {
+                        index: tape.len(),
+                        kind: ParseErrorKind::EmptyElement { line: line_number },
+                    });
+                }
+                after_element = false;
+                open_comma = Some(line_number);
+            }
+            for text in part.split_whitespace() {
+                let value = text.parse().map_err(|_| ParseError {
+                    index: tape.len(),
+                    kind: ParseErrorKind::InvalidNumber {
+                        line: line_number,
+                        text: text.to_string(),
+                    },
+                })?;
+                tape.push(value);
+                after_element = true;
+                open_comma = None;
+            }
+        }
+    }
+    match open_comma {
+        Some(line) => Err(ParseError {
+            index: tape.len(),
+            kind: ParseErrorKind::EmptyElement { line },
+        }),
+        None => Ok(tape),
+    }
+}
+
+pub fn encode_compact(tape: &[isize]) -> Vec<u8> {
+    let mut bytes = COMPACT_MAGIC.to_vec();
+    write_varint(&mut bytes, tape.len() as u64);
+    for &value in tape {
+        let zigzag = ((value as i64) << 1) ^ ((value as i64) >> 63);
+        write_varint(&mut bytes, zigzag as u64);
+    }
+    bytes
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &mut impl Iterator<Item = u8>, index: usize) -> Result<u64, ParseError> {
+    let error = |kind| ParseError { index, kind };
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = bytes
+            .next()
+            .ok_or_else(|| error(ParseErrorKind::Truncated))?;
+        let bits = (byte & 0x7f) as u64;
+        if shift == 63 && bits > 1 {
+            return Err(error(ParseErrorKind::Overflow));
+        }
+        value |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(error(ParseErrorKind::Overflow))
+}
+
+/// Decodes a tape written by `encode_compact`, header included.
+pub fn decode_compact(bytes: &[u8]) -> Result<Tape, ParseError> {
+    let mut bytes = bytes
+        .strip_prefix(COMPACT_MAGIC)
+        .ok_or(ParseError {
+            index: 0,
+            kind: ParseErrorKind::NotCompact,
+        })?
+        .iter()
+        .copied();
+    let len = read_varint(&mut bytes, 0)? as usize;
+    let mut tape = Tape::with_capacity(len.min(1 << 20));
+    for index in 0..len {
+        let zigzag = read_varint(&mut bytes, index)?;
+        let value = ((zigzag >> 1) as i64) ^ -((zigzag & 1) as i64);
+        let value = isize::try_from(value).map_err(|_| ParseError {
+            index,
+            kind: ParseErrorKind::Overflow,
+        })?;
+        tape.push(value);
+    }
+    if bytes.next().is_some() {
+        return Err(ParseError {
+            index: len,
+            kind: ParseErrorKind::TrailingBytes,
+        });
+    }
+    Ok(tape)
+}
+
+/// Reads a program in either format, telling them apart by the compact header.
+pub fn load_intcode_program(bytes: &[u8]) -> Result<Tape, ParseError> {
+    if bytes.starts_with(COMPACT_MAGIC) {
+        decode_compact(bytes)
+    } else {
+        try_parse_intcode_program(&String::from_utf8_lossy(bytes))
+    }
+}
+
+/// Reads the program a tool was pointed at: a day number fetches that day's input, anything
+/// else is a program file in either format. Panics if it can't.
+pub fn load_day_or_file(arg: &str) -> Tape {
+    match arg.parse::<u8>() {
+        Ok(day) => parse_intcode_program(&crate::aoc_input::get_input(day)),
+        Err(_) => {
+            let bytes = std::fs::read(arg).expect("Failed reading program file");
+            load_intcode_program(&bytes).unwrap_or_else(|e| panic!("{}: {}", arg, e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(input: &str) -> ParseError {
+        try_parse_intcode_program(input).unwrap_err()
+    }
+
+    #[test]
+    fn test_parse_text() {
+        assert_eq!(
+            try_parse_intcode_program("1,0,0,3,99"),
+            Ok(vec![1, 0, 0, 3, 99])
+        );
+        assert_eq!(
+            try_parse_intcode_program("1,0,0,3,99\n"),
+            Ok(vec![1, 0, 0, 3, 99])
+        );
+        assert_eq!(try_parse_intcode_program(""), Ok(vec![]));
+        let relaxed = "; header\n104, -7 # print\n,\n99 204 1\n";
+        assert_eq!(
+            try_parse_intcode_program(relaxed),
+            Ok(vec![104, -7, 99, 204, 1])
+        );
+    }
+
+    #[test]
+    fn test_parse_text_errors() {
+        assert_eq!(
+            error("1,2\n3,x4"),
+            ParseError {
+                index: 3,
+                kind: ParseErrorKind::InvalidNumber {
+                    line: 2,
+                    text: "x4".to_string(),
+                },
+            }
+        );
+        assert_eq!(
+            error("1,,2"),
+            ParseError {
+                index: 1,
+                kind: ParseErrorKind::EmptyElement { line: 1 },
+            }
+        );
+        assert_eq!(
+            error(",1"),
+            ParseError {
+                index: 0,
+                kind: ParseErrorKind::EmptyElement { line: 1 },
+            }
+        );
+        assert_eq!(
+            error("1,2,\n; done\n"),
+            ParseError {
+                index: 2,
+                kind: ParseErrorKind::EmptyElement { line: 1 },
+            }
+        );
+        assert_eq!(
+            error("1,\n# 2\n,3").kind,
+            ParseErrorKind::EmptyElement { line: 3 }
+        );
+        assert_eq!(
+            error("99999999999999999999999").kind,
+            ParseErrorKind::InvalidNumber {
+                line: 1,
+                text: "99999999999999999999999".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_compact_round_trip() {
+        let tape = vec![
+            0,
+            1,
+            -1,
+            63,
+            -64,
+            64,
+            109,
+            -1_000_000,
+            isize::MAX,
+            isize::MIN,
+        ];
+        let bytes = encode_compact(&tape);
+        assert!(bytes.starts_with(COMPACT_MAGIC));
+        assert_eq!(decode_compact(&bytes), Ok(tape.clone()));
+        assert_eq!(load_intcode_program(&bytes), Ok(tape));
+        assert_eq!(load_intcode_program(b"104,5,99\n"), Ok(vec![104, 5, 99]));
+    }
+
+    #[test]
+    fn test_compact_errors() {
+        let kind = |bytes: &[u8]| decode_compact(bytes).unwrap_err().kind;
+        let bytes = encode_compact(&[1, 300, 99]);
+
+        assert_eq!(kind(b"1,2,3"), ParseErrorKind::NotCompact);
+        assert_eq!(kind(COMPACT_MAGIC), ParseErrorKind::Truncated);
+        assert_eq!(
+            decode_compact(&bytes[..bytes.len() - 3]),
+            Err(ParseError {
+                index: 1,
+                kind: ParseErrorKind::Truncated,
+            })
+        );
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            decode_compact(&trailing),
+            Err(ParseError {
+                index: 3,
+                kind: ParseErrorKind::TrailingBytes,
+            })
+        );
+
+        let mut overflow = COMPACT_MAGIC.to_vec();
+        overflow.push(1);
+        overflow.extend(&[0xff; 9]);
+        overflow.push(0x7f);
+        assert_eq!(kind(&overflow), ParseErrorKind::Overflow);
+    }
+}
@@ -0,0 +1,254 @@
+//! Machine memory: a dense vector for the program and whatever it touches nearby, plus
+//! fixed-size pages for scattered far-away addresses, so writing to address 10^9 costs one
+//! page rather than gigabytes. Words are `isize` unless a wider type is asked for, as
+//! `wide::WideMachine` does.
+
+use std::collections::HashMap;
+
+/// Words per sparse page.
+pub const PAGE_SIZE: usize = 1024;
+
+/// Allocating memory for `addr` would exceed the machine's memory limit.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LimitExceeded {
+    pub addr: usize,
+    pub limit: usize,
+}
+
+/// Two memories are equal when every address reads the same, however each is laid out and
+/// whatever their limits.
+#[derive(Debug, Clone)]
+pub struct Memory<W = isize> {
+    /// Covers addresses `0..dense.len()`.
+    dense: Vec<W>,
+    /// Keyed by page number; every page lies entirely past the end of `dense`.
+    pages: HashMap<usize, Box<[W]>>,
+    /// Maximum number of words to allocate, counting whole pages.
+    limit: Option<usize>,
+}
+
+impl<W: Copy + Default + PartialEq> Memory<W> {
+    pub fn new(tape: Vec<W>) -> Self {
+        Memory {
+            dense: tape,
+            pages: HashMap::new(),
+            limit: None,
+        }
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Caps the number of words this memory may allocate. Words already allocated are kept
+    /// even if they exceed the new limit.
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    /// Number of words currently allocated.
+    pub fn allocated(&self) -> usize {
+        self.dense.len() + self.pages.len() * PAGE_SIZE
+    }
+
+    /// The contiguous low region, which always starts with the loaded program.
+    pub fn dense(&self) -> &[W] {
+        &self.dense
+    }
+
+    /// Never-written addresses read as 0.
+    #[inline]
+    pub fn get(&self, addr: usize) -> W {
+        match self.dense.get(addr) {
+            Some(&value) => value,
+            None => self.get_paged(addr),
+        }
+    }
+
+    fn get_paged(&self, addr: usize) -> W {
+        match self.pages.get(&(addr / PAGE_SIZE)) {
+            Some(page) => page[addr % PAGE_SIZE],
+            None => W::default(),
+        }
+    }
+
+    /// Stores `value` at `addr`, returning the previous value.
+    #[inline]
+    pub fn set(&mut self, addr: usize, value: W) -> Result<W, LimitExceeded> {
+        if let Some(word) = self.dense.get_mut(addr) {
+            return Ok(std::mem::replace(word, value));
+        }
+        self.set_slow(addr, value)
+    }
+
+    #[cold]
+    fn set_slow(&mut self, addr: usize, value: W) -> Result<W, LimitExceeded> {
+        // Writing 0 to a page that doesn't exist yet changes nothing.
+        if value == W::default() && !self.pages.contains_key(&(addr / PAGE_SIZE)) {
+            return Ok(value);
+        }
+
+        // Programs mostly touch memory just past their own code, so the dense region grows
+        // to cover anything within twice its size and only addresses further out are paged.
+        if addr < 2 * self.dense.len().max(PAGE_SIZE) {
+            self.grow_dense(addr)?;
+            return Ok(std::mem::replace(&mut self.dense[addr], value));
+        }
+
+        let page = addr / PAGE_SIZE;
+        if !self.pages.contains_key(&page) {
+            self.reserve(addr, PAGE_SIZE)?;
+            self.pages
+                .insert(page, vec![W::default(); PAGE_SIZE].into_boxed_slice());
+        }
+        let word = &mut self.pages.get_mut(&page).unwrap()[addr % PAGE_SIZE];
+        Ok(std::mem::replace(word, value))
+    }
+
+    /// Whether every word in `other`'s pages reads the same here.
+    fn matches_pages_of(&self, other: &Memory<W>) -> bool {
+        other.pages.iter().all(|(&page, words)| {
+            let start = page * PAGE_SIZE;
+            (start..)
+                .zip(words.iter())
+                .all(|(addr, &word)| self.get(addr) == word)
+        })
+    }
+
+    fn reserve(&self, addr: usize, words: usize) -> Result<(), LimitExceeded> {
+        match self.limit {
+            Some(limit) if self.allocated() + words > limit => Err(LimitExceeded { addr, limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Extends the dense region to a page boundary past `addr`, folding in any pages it
+    /// now covers.
+    fn grow_dense(&mut self, addr: usize) -> Result<(), LimitExceeded> {
+        let old_len = self.dense.len();
+        let new_len = (addr / PAGE_SIZE + 1) * PAGE_SIZE;
+        let absorbed: Vec<usize> = self
+            .pages
+            .keys()
+            .copied()
+            .filter(|&page| page * PAGE_SIZE < new_len)
+            .collect();
+        self.reserve(addr, new_len - old_len - absorbed.len() * PAGE_SIZE)?;
+
+        self.dense.resize(new_len, W::default());
+        for page in absorbed {
+            let start = page * PAGE_SIZE;
+            let words = self.pages.remove(&page).unwrap();
+            self.dense[start..start + PAGE_SIZE].copy_from_slice(&words);
+        }
+        Ok(())
+    }
+}
+
+impl<W: Copy + Default + PartialEq> PartialEq for Memory<W> {
+    fn eq(&self, other: &Memory<W>) -> bool {
+        let (shorter, longer) = if self.dense.len() <= other.dense.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let common = shorter.dense.len();
+        shorter.dense[..] == longer.dense[..common]
+            && (common..longer.dense.len()).all(|addr| shorter.get(addr) == longer.dense[addr])
+            && self.matches_pages_of(other)
+            && other.matches_pages_of(self)
+    }
+}
+
+impl<W: Copy + Default + Eq> Eq for Memory<W> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAR: usize = 1_000_000_000;
+
+    #[test]
+    fn test_far_writes_are_paged() {
+        let mut memory = Memory::new(vec![1, 2, 3]);
+        assert_eq!(memory.set(FAR, 5), Ok(0));
+        assert_eq!(memory.get(FAR), 5);
+        assert_eq!(memory.get(FAR + 1), 0);
+        assert_eq!(memory.get(FAR - 1), 0);
+        assert_eq!(memory.dense(), &[1, 2, 3]);
+        assert_eq!(memory.allocated(), 3 + PAGE_SIZE);
+        assert_eq!(memory.set(FAR, 6), Ok(5));
+    }
+
+    #[test]
+    fn test_near_writes_grow_the_dense_region() {
+        let mut memory = Memory::new(vec![1, 2, 3]);
+        assert_eq!(memory.set(1500, 7), Ok(0));
+        assert_eq!(memory.dense().len(), 2 * PAGE_SIZE);
+        assert_eq!(memory.get(1500), 7);
+        assert_eq!(memory.allocated(), 2 * PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_dense_region_absorbs_pages() {
+        let mut memory = Memory::new(vec![0; PAGE_SIZE]);
+        memory.set(5000, 9).unwrap();
+        memory.set(2000, 1).unwrap();
+        memory.set(4000, 1).unwrap();
+        assert_eq!(memory.dense().len(), 4 * PAGE_SIZE);
+        assert_eq!(memory.allocated(), 5 * PAGE_SIZE);
+        memory.set(5001, 2).unwrap();
+        assert_eq!(memory.dense().len(), 5 * PAGE_SIZE);
+        assert_eq!(memory.allocated(), 5 * PAGE_SIZE);
+        assert_eq!(&memory.dense()[4999..5002], &[0, 9, 2]);
+    }
+
+    #[test]
+    fn test_zero_writes_allocate_nothing() {
+        let mut memory = Memory::new(vec![1]);
+        assert_eq!(memory.set(FAR, 0), Ok(0));
+        assert_eq!(memory.set(2000, 0), Ok(0));
+        assert_eq!(memory.allocated(), 1);
+    }
+
+    #[test]
+    fn test_limit() {
+        let mut memory = Memory::new(vec![0; 10]);
+        memory.set_limit(Some(2000));
+        assert_eq!(memory.set(FAR, 1), Ok(0));
+        assert_eq!(
+            memory.set(2 * FAR, 1),
+            Err(LimitExceeded {
+                addr: 2 * FAR,
+                limit: 2000,
+            })
+        );
+        assert_eq!(memory.get(2 * FAR), 0);
+        // Pages that already exist can still be written.
+        assert_eq!(memory.set(FAR + 1, 1), Ok(0));
+    }
+
+    #[test]
+    fn test_equality_ignores_layout() {
+        let mut paged = Memory::new(vec![0; PAGE_SIZE]);
+        paged.set(5000, 9).unwrap();
+        let mut dense = Memory::new(vec![0; 6000]);
+        dense.set(5000, 9).unwrap();
+        assert_eq!(paged, dense);
+        assert_eq!(dense, paged);
+
+        dense.set(5999, 1).unwrap();
+        assert_ne!(paged, dense);
+        assert_ne!(dense, paged);
+
+        let mut cleared = Memory::new(vec![1, 2]);
+        cleared.set(FAR, 3).unwrap();
+        assert_ne!(cleared, Memory::new(vec![1, 2, 0, 0]));
+        cleared.set(FAR, 0).unwrap();
+        assert_eq!(cleared, Memory::new(vec![1, 2, 0, 0]));
+
+        let mut limited = Memory::new(vec![1, 2]);
+        limited.set_limit(Some(10));
+        assert_eq!(limited, Memory::new(vec![1, 2]));
+    }
+}
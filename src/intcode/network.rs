@@ -0,0 +1,631 @@
+//! Several machines wired together. Each node's outputs either feed the inputs of the nodes
+//! it is linked to, or are grouped into packets routed by destination address, as on day
+//! 23. A network runs until it needs attention from outside: everything halted, nothing can
+//! make progress, nothing but polling has happened for a while, or a packet has nowhere to
+//! go.
+
+use super::*;
+
+pub type NodeId = usize;
+
+/// Default for `Network::set_stall_rounds`.
+pub const DEFAULT_STALL_ROUNDS: usize = 1000;
+
+/// Where a node's outputs go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    /// Outputs stay in the node's output stream for the caller to collect.
+    Keep,
+    /// Every output is appended to the input of each linked node.
+    Links(Vec<NodeId>),
+    /// Every `size` outputs form a packet: a destination address followed by the payload.
+    Packets(usize),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Scheduler {
+    /// Resumes every node in turn, each running until it blocks or halts, or for at most
+    /// `quantum` instructions.
+    RoundRobin { quantum: Option<usize> },
+    /// Only resumes nodes that have input waiting, in the order it arrived. Polling nodes are
+    /// resumed when nothing else is ready.
+    EventDriven,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub from: NodeId,
+    pub address: isize,
+    pub payload: Vec<isize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkEvent {
+    /// Every node has halted.
+    Halted,
+    /// Every node that hasn't halted is blocked on an empty input, so none ever will
+    /// run again.
+    Deadlocked(Vec<Wait>),
+    /// Every node is blocked, and the polling ones are provably idle: polling brings them
+    /// back to exactly the state they polled from without sending anything.
+    Idle,
+    /// Every node is blocked and nothing has been output for this many rounds, but polling
+    /// keeps changing some node's state, so it can't be proved idle. Unlike `Idle`, this
+    /// never wakes the NAT; whether to deliver its packet is up to the caller.
+    Stalled(usize),
+    /// The NAT woke the idle network by delivering this payload.
+    NatDelivered(Vec<isize>),
+    /// A packet was addressed to no node.
+    Undeliverable(Packet),
+    Fault {
+        node: NodeId,
+        error: IntcodeError,
+    },
+}
+
+/// A node sending to a blocked one, as listed in a `Wait`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sender {
+    pub node: NodeId,
+    pub name: String,
+    pub status: StopStatus,
+}
+
+/// A node blocked on an empty input, along with every node linked to that input or able to
+/// send it packets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wait {
+    pub node: NodeId,
+    pub name: String,
+    pub senders: Vec<Sender>,
+}
+
+impl fmt::Display for Wait {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} waits on ", self.name)?;
+        if self.senders.is_empty() {
+            return write!(f, "nothing");
+        }
+        for (i, sender) in self.senders.iter().enumerate() {
+            let status = match sender.status {
+                StopStatus::Halted => "halted",
+                StopStatus::BlockedOnInput => "waiting",
+                _ => "running",
+            };
+            let separator = if i > 0 { ", " } else { "" };
+            write!(f, "{}{} ({})", separator, sender.name, status)?;
+        }
+        Ok(())
+    }
+}
+
+/// Keeps the last packet sent to `address` and, whenever the network goes idle, delivers
+/// it to the node at `target`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nat {
+    pub address: isize,
+    pub target: isize,
+    pub first_received: Option<Vec<isize>>,
+    pub last_received: Option<Vec<isize>>,
+}
+
+#[derive(Debug)]
+pub struct Node {
+    pub name: String,
+    pub machine: IntcodeMachine,
+    address: Option<isize>,
+    route: Route,
+    poll: Option<isize>,
+    /// False until the node first runs; its status means nothing before then.
+    started: bool,
+    status: StopStatus,
+    last_output: Option<isize>,
+    /// The node's state when it was last given the poll value, until it waits again.
+    poll_start: Option<MachineSnapshot>,
+    /// Set when a poll led straight back to `poll_start` without sending anything. Polling
+    /// again would do the same, so the node is left alone until it gets input.
+    idle: bool,
+    /// Start of a packet still being written.
+    partial: Vec<isize>,
+}
+
+impl Node {
+    pub fn address(&self) -> Option<isize> {
+        self.address
+    }
+
+    pub fn route(&self) -> &Route {
+        &self.route
+    }
+
+    pub fn status(&self) -> StopStatus {
+        self.status
+    }
+
+    /// The most recent value the node produced, wherever it went.
+    pub fn last_output(&self) -> Option<isize> {
+        self.last_output
+    }
+
+    fn waiting(&self) -> bool {
+        self.started
+            && self.status == StopStatus::BlockedOnInput
+            && self.machine.input.borrow().is_empty()
+    }
+}
+
+#[derive(Debug)]
+pub struct Network {
+    nodes: Vec<Node>,
+    scheduler: Scheduler,
+    nat: Option<Nat>,
+    ready: VecDeque<NodeId>,
+    undeliverable: VecDeque<Packet>,
+    stall_rounds: usize,
+    /// Rounds in a row in which every node was blocked and none output anything.
+    quiet_rounds: usize,
+}
+
+impl Default for Network {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Network {
+    pub fn new() -> Self {
+        Network {
+            nodes: Vec::new(),
+            scheduler: Scheduler::RoundRobin { quantum: None },
+            nat: None,
+            ready: VecDeque::new(),
+            undeliverable: VecDeque::new(),
+            stall_rounds: DEFAULT_STALL_ROUNDS,
+            quiet_rounds: 0,
+        }
+    }
+
+    pub fn add_node(&mut self, name: impl Into<String>, machine: IntcodeMachine) -> NodeId {
+        self.nodes.push(Node {
+            name: name.into(),
+            machine,
+            address: None,
+            route: Route::Keep,
+            poll: None,
+            started: false,
+            status: StopStatus::BlockedOnInput,
+            last_output: None,
+            poll_start: None,
+            idle: false,
+            partial: Vec::new(),
+        });
+        self.ready.push_back(self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id]
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id]
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.iter()
+    }
+
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.nodes.iter().position(|node| node.name == name)
+    }
+
+    /// Makes `from`'s outputs also go to `to`'s input.
+    pub fn connect(&mut self, from: NodeId, to: NodeId) {
+        match &mut self.nodes[from].route {
+            Route::Links(links) => links.push(to),
+            route => *route = Route::Links(vec![to]),
+        }
+    }
+
+    pub fn set_route(&mut self, id: NodeId, route: Route) {
+        if let Route::Packets(size) = route {
+            assert!(size > 0, "packets need at least an address");
+        }
+        self.nodes[id].route = route;
+    }
+
+    /// Address packets are delivered to this node by.
+    pub fn set_address(&mut self, id: NodeId, address: Option<isize>) {
+        self.nodes[id].address = address;
+    }
+
+    /// Value the node reads instead of blocking when its input is empty, like the -1 a day
+    /// 23 NIC gets when there are no packets.
+    pub fn set_poll(&mut self, id: NodeId, value: Option<isize>) {
+        self.nodes[id].poll = value;
+    }
+
+    pub fn set_scheduler(&mut self, scheduler: Scheduler) {
+        self.scheduler = scheduler;
+    }
+
+    /// Rounds without output after which `run` gives up on pollers that never settle and
+    /// reports `Stalled`. Idle detection only works for pollers that come back to the same
+    /// state.
+    pub fn set_stall_rounds(&mut self, rounds: usize) {
+        assert!(rounds > 0, "a network needs at least one round to stall");
+        self.stall_rounds = rounds;
+    }
+
+    pub fn set_nat(&mut self, address: isize, target: isize) {
+        self.nat = Some(Nat {
+            address,
+            target,
+            first_received: None,
+            last_received: None,
+        });
+    }
+
+    pub fn nat(&self) -> Option<&Nat> {
+        self.nat.as_ref()
+    }
+
+    /// Appends values to the node's input.
+    pub fn send(&mut self, id: NodeId, values: &[isize]) {
+        let node = &mut self.nodes[id];
+        node.machine.input.borrow_mut().extend(values);
+        node.poll_start = None;
+        node.idle = false;
+        if !self.ready.contains(&id) {
+            self.ready.push_back(id);
+        }
+    }
+
+    fn deliver(&mut self, packet: Packet) {
+        if let Some(nat) = &mut self.nat {
+            if packet.address == nat.address {
+                nat.first_received.get_or_insert(packet.payload.clone());
+                nat.last_received = Some(packet.payload);
+                return;
+            }
+        }
+        match self
+            .nodes
+            .iter()
+            .position(|node| node.address == Some(packet.address))
+        {
+            Some(id) => self.send(id, &packet.payload),
+            None => self.undeliverable.push_back(packet),
+        }
+    }
+
+    fn route(&mut self, id: NodeId, outputs: Vec<isize>) {
+        match self.nodes[id].route.clone() {
+            Route::Keep => (),
+            _ if outputs.is_empty() => (),
+            Route::Links(links) => {
+                for to in links {
+                    self.send(to, &outputs);
+                }
+            }
+            Route::Packets(size) => {
+                for value in outputs {
+                    let node = &mut self.nodes[id];
+                    node.partial.push(value);
+                    if node.partial.len() == size {
+                        let mut payload = std::mem::take(&mut node.partial);
+                        let address = payload.remove(0);
+                        self.deliver(Packet {
+                            from: id,
+                            address,
+                            payload,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs the node until it stops. Returns false if it couldn't run at all.
+    fn resume(&mut self, id: NodeId, quantum: Option<usize>) -> IntcodeResult<bool> {
+        let node = &mut self.nodes[id];
+        match (node.status, node.waiting(), node.poll) {
+            (StopStatus::Halted, _, _) | (_, true, None) => return Ok(false),
+            (_, true, Some(_)) if node.idle => return Ok(false),
+            (_, true, Some(value)) => {
+                node.poll_start = Some(node.machine.snapshot());
+                node.machine.input.borrow_mut().push_back(value);
+            }
+            _ => (),
+        }
+
+        node.started = true;
+        let output_len = node.machine.output.borrow().len();
+        node.status = match quantum {
+            Some(quantum) => node.machine.run_for(quantum),
+            None => node.machine.run(),
+        }?;
+        if node.machine.output.borrow().len() > output_len {
+            self.quiet_rounds = 0;
+        }
+        let outputs: Vec<isize> = match node.route {
+            Route::Keep => {
+                node.last_output = node
+                    .machine
+                    .output
+                    .borrow()
+                    .back()
+                    .copied()
+                    .or(node.last_output);
+                Vec::new()
+            }
+            _ => node.machine.output.borrow_mut().drain(..).collect(),
+        };
+        if let Some(&value) = outputs.last() {
+            node.last_output = Some(value);
+        }
+        if !outputs.is_empty() {
+            node.poll_start = None;
+        } else if node.waiting() {
+            if let Some(start) = node.poll_start.take() {
+                let machine = &node.machine;
+                node.idle = start.pc == machine.pc()
+                    && start.bp == machine.bp()
+                    && &start.memory == machine.memory();
+            }
+        }
+        self.route(id, outputs);
+        Ok(true)
+    }
+
+    /// Nodes that could send to `id`: those linked to it, plus any routing packets if it
+    /// has an address.
+    fn senders(&self, id: NodeId) -> Vec<Sender> {
+        let addressed = self.nodes[id].address.is_some();
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| match &node.route {
+                Route::Keep => false,
+                Route::Links(links) => links.contains(&id),
+                Route::Packets(_) => addressed,
+            })
+            .map(|(from, node)| Sender {
+                node: from,
+                name: node.name.clone(),
+                status: node.status,
+            })
+            .collect()
+    }
+
+    fn waits(&self) -> Vec<Wait> {
+        (0..self.nodes.len())
+            .filter(|&id| self.nodes[id].status != StopStatus::Halted)
+            .map(|id| Wait {
+                node: id,
+                name: self.nodes[id].name.clone(),
+                senders: self.senders(id),
+            })
+            .collect()
+    }
+
+    /// The event to report if no node can make progress.
+    fn quiescent(&mut self) -> Option<NetworkEvent> {
+        let stuck = self.nodes.iter().all(|node| match node.poll {
+            _ if node.status == StopStatus::Halted => true,
+            None => node.waiting(),
+            Some(_) => node.idle && node.waiting(),
+        });
+        if !stuck {
+            return None;
+        }
+        if self
+            .nodes
+            .iter()
+            .all(|node| node.status == StopStatus::Halted)
+        {
+            return Some(NetworkEvent::Halted);
+        }
+        let polling = self
+            .nodes
+            .iter()
+            .any(|node| node.status != StopStatus::Halted && node.poll.is_some());
+        if !polling {
+            return Some(NetworkEvent::Deadlocked(self.waits()));
+        }
+
+        let wake = self
+            .nat
+            .as_ref()
+            .and_then(|nat| Some((nat.target, nat.last_received.clone()?)));
+        match wake {
+            Some((target, payload)) => {
+                self.deliver(Packet {
+                    from: self.nodes.len(),
+                    address: target,
+                    payload: payload.clone(),
+                });
+                Some(NetworkEvent::NatDelivered(payload))
+            }
+            None => Some(NetworkEvent::Idle),
+        }
+    }
+
+    /// Counts rounds in which only polling happened, returning an event once there have
+    /// been too many in a row.
+    fn stalled(&mut self) -> Option<NetworkEvent> {
+        let quiet = self
+            .nodes
+            .iter()
+            .all(|node| node.status == StopStatus::Halted || node.waiting());
+        if !quiet {
+            self.quiet_rounds = 0;
+            return None;
+        }
+        self.quiet_rounds += 1;
+        if self.quiet_rounds < self.stall_rounds {
+            return None;
+        }
+        Some(NetworkEvent::Stalled(self.stall_rounds))
+    }
+
+    /// Runs the network until it needs attention; see `NetworkEvent`. Call again to carry on.
+    pub fn run(&mut self) -> NetworkEvent {
+        loop {
+            if let Some(packet) = self.undeliverable.pop_front() {
+                return NetworkEvent::Undeliverable(packet);
+            }
+
+            let scheduled: Vec<NodeId> = match self.scheduler {
+                Scheduler::RoundRobin { .. } => (0..self.nodes.len()).collect(),
+                Scheduler::EventDriven => match self.ready.pop_front() {
+                    Some(id) => vec![id],
+                    None => (0..self.nodes.len())
+                        .filter(|&id| self.nodes[id].poll.is_some())
+                        .collect(),
+                },
+            };
+            let quantum = match self.scheduler {
+                Scheduler::RoundRobin { quantum } => quantum,
+                Scheduler::EventDriven => None,
+            };
+            for id in scheduled {
+                if let Err(error) = self.resume(id, quantum) {
+                    return NetworkEvent::Fault { node: id, error };
+                }
+            }
+
+            if self.scheduler == Scheduler::EventDriven && !self.ready.is_empty() {
+                continue;
+            }
+            if let Some(event) = self.quiescent().or_else(|| self.stalled()) {
+                self.quiet_rounds = 0;
+                return event;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ECHO: &str = "3,0,4,0,99";
+    const SCHEDULERS: [Scheduler; 2] = [
+        Scheduler::RoundRobin { quantum: None },
+        Scheduler::EventDriven,
+    ];
+
+    fn node(program: &str) -> IntcodeMachine {
+        IntcodeMachine::new(parse_intcode_program(program))
+    }
+
+    #[test]
+    fn test_output_before_input() {
+        let mut network = Network::new();
+        let id = network.add_node("source", node("104,1,99"));
+        assert_eq!(network.run(), NetworkEvent::Halted);
+        assert_eq!(network.node(id).last_output(), Some(1));
+    }
+
+    #[test]
+    fn test_source_linked_to_echo() {
+        for &scheduler in &SCHEDULERS {
+            for &echo_first in &[false, true] {
+                let mut network = Network::new();
+                network.set_scheduler(scheduler);
+                let (source, echo) = if echo_first {
+                    let echo = network.add_node("echo", node(ECHO));
+                    (network.add_node("source", node("104,42,99")), echo)
+                } else {
+                    let source = network.add_node("source", node("104,42,99"));
+                    (source, network.add_node("echo", node(ECHO)))
+                };
+                network.connect(source, echo);
+                assert_eq!(network.run(), NetworkEvent::Halted);
+                assert_eq!(network.node(echo).last_output(), Some(42));
+            }
+        }
+    }
+
+    #[test]
+    fn test_deadlock() {
+        let mut network = Network::new();
+        let a = network.add_node("a", node(ECHO));
+        let b = network.add_node("b", node(ECHO));
+        network.connect(a, b);
+        network.connect(b, a);
+        let waits = match network.run() {
+            NetworkEvent::Deadlocked(waits) => waits,
+            event => panic!("expected a deadlock, got {:?}", event),
+        };
+        let waits: Vec<String> = waits.iter().map(|wait| wait.to_string()).collect();
+        assert_eq!(waits, ["a waits on b (waiting)", "b waits on a (waiting)"]);
+    }
+
+    /// Reads until it gets something other than -1, then sends it to address 255 twice in
+    /// one packet. Polling leaves its memory unchanged once it has polled once.
+    const RELAY: &str = "3,100,1008,100,-1,101,1005,101,0,104,255,4,100,4,100,1105,1,0";
+
+    #[test]
+    fn test_idle() {
+        let mut network = Network::new();
+        let relay = network.add_node("relay", node(RELAY));
+        network.set_route(relay, Route::Packets(3));
+        network.set_poll(relay, Some(-1));
+        assert_eq!(network.run(), NetworkEvent::Idle);
+    }
+
+    #[test]
+    fn test_nat_wakes_idle_network() {
+        for &scheduler in &SCHEDULERS {
+            let mut network = Network::new();
+            network.set_scheduler(scheduler);
+            let relay = network.add_node("relay", node(RELAY));
+            network.set_address(relay, Some(0));
+            network.set_route(relay, Route::Packets(3));
+            network.set_poll(relay, Some(-1));
+            network.set_nat(255, 0);
+            network.send(relay, &[5]);
+
+            assert_eq!(network.run(), NetworkEvent::NatDelivered(vec![5, 5]));
+            assert_eq!(network.nat().unwrap().first_received, Some(vec![5, 5]));
+            assert_eq!(network.run(), NetworkEvent::NatDelivered(vec![5, 5]));
+        }
+    }
+
+    /// Counts how many times it has polled, so it never comes back to the same state.
+    const COUNTER: &str = "3,100,1001,101,1,101,1105,1,0";
+
+    #[test]
+    fn test_never_idle_poller() {
+        for &scheduler in &SCHEDULERS {
+            let mut network = Network::new();
+            network.set_scheduler(scheduler);
+            network.set_stall_rounds(50);
+            let counter = network.add_node("counter", node(COUNTER));
+            network.set_poll(counter, Some(-1));
+            assert_eq!(network.run(), NetworkEvent::Stalled(50));
+            let polls = network.node(counter).machine.memory().get(101);
+            assert_eq!(network.run(), NetworkEvent::Stalled(50));
+            assert_eq!(network.node(counter).machine.memory().get(101), polls + 50);
+        }
+    }
+
+    #[test]
+    fn test_nat_ignores_stalled_network() {
+        let mut network = Network::new();
+        network.set_stall_rounds(10);
+        let relay = network.add_node("relay", node(RELAY));
+        network.set_route(relay, Route::Packets(3));
+        network.set_address(relay, Some(0));
+        network.set_poll(relay, Some(-1));
+        let counter = network.add_node("counter", node(COUNTER));
+        network.set_poll(counter, Some(-1));
+        network.set_nat(255, 0);
+        network.send(relay, &[7]);
+        assert_eq!(network.run(), NetworkEvent::Stalled(10));
+        assert_eq!(network.nat().unwrap().last_received, Some(vec![7, 7]));
+        assert_eq!(network.run(), NetworkEvent::Stalled(10));
+    }
+}
@@ -0,0 +1,351 @@
+//! Ready-made `IntcodeInput`/`IntcodeOutput` implementations beyond `StreamRef`.
+
+use super::*;
+use std::io::{self, BufRead, Write};
+
+impl<W> IntcodeInput<W> for VecDeque<W> {
+    fn read(&mut self) -> Option<W> {
+        self.pop_front()
+    }
+}
+
+impl<W> IntcodeOutput<W> for VecDeque<W> {
+    fn write(&mut self, value: W) {
+        self.push_back(value);
+    }
+}
+
+impl<W> IntcodeOutput<W> for Vec<W> {
+    fn write(&mut self, value: W) {
+        self.push(value);
+    }
+}
+
+impl<W, T: IntcodeInput<W> + ?Sized> IntcodeInput<W> for Box<T> {
+    fn read(&mut self) -> Option<W> {
+        (**self).read()
+    }
+}
+
+impl<W, T: IntcodeOutput<W> + ?Sized> IntcodeOutput<W> for Box<T> {
+    fn write(&mut self, value: W) {
+        (**self).write(value)
+    }
+}
+
+impl<W, T: IntcodeInput<W> + ?Sized> IntcodeInput<W> for &mut T {
+    fn read(&mut self) -> Option<W> {
+        (**self).read()
+    }
+}
+
+impl<W, T: IntcodeOutput<W> + ?Sized> IntcodeOutput<W> for &mut T {
+    fn write(&mut self, value: W) {
+        (**self).write(value)
+    }
+}
+
+/// Input answered on demand by a closure; returning `None` blocks the machine.
+pub struct FnInput<F>(pub F);
+
+/// Output handed straight to a closure as it is produced.
+pub struct FnOutput<F>(pub F);
+
+impl<F: FnMut() -> Option<isize>> IntcodeInput for FnInput<F> {
+    fn read(&mut self) -> Option<isize> {
+        (self.0)()
+    }
+}
+
+impl<F: FnMut(isize)> IntcodeOutput for FnOutput<F> {
+    fn write(&mut self, value: isize) {
+        (self.0)(value)
+    }
+}
+
+pub fn input_fn<F: FnMut() -> Option<isize>>(f: F) -> FnInput<F> {
+    FnInput(f)
+}
+
+pub fn output_fn<F: FnMut(isize)>(f: F) -> FnOutput<F> {
+    FnOutput(f)
+}
+
+/// Input drawn from an iterator; the machine blocks once it is exhausted.
+pub struct IterInput<T>(pub T);
+
+impl<T: Iterator<Item = isize>> IntcodeInput for IterInput<T> {
+    fn read(&mut self) -> Option<isize> {
+        self.0.next()
+    }
+}
+
+pub fn input_iter<T: IntoIterator<Item = isize>>(iter: T) -> IterInput<T::IntoIter> {
+    IterInput(iter.into_iter())
+}
+
+/// Values above this are not ASCII and are kept aside as raw values by `AsciiOutput`.
+pub const ASCII_MAX: isize = 127;
+
+/// Feeds text to the machine one character code at a time.
+#[derive(Debug, Default, Clone)]
+pub struct AsciiInput {
+    pending: Stream,
+}
+
+impl AsciiInput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_str(&mut self, text: &str) {
+        self.pending.extend(text.bytes().map(|b| b as isize));
+    }
+
+    /// Queues `line` followed by a newline, which is how ASCII programs expect commands.
+    pub fn push_line(&mut self, line: &str) {
+        self.push_str(line);
+        self.pending.push_back('\n' as isize);
+    }
+}
+
+impl IntcodeInput for AsciiInput {
+    fn read(&mut self) -> Option<isize> {
+        self.pending.pop_front()
+    }
+}
+
+/// Collects output as text, setting aside any values that aren't ASCII characters.
+#[derive(Debug, Default, Clone)]
+pub struct AsciiOutput {
+    pub text: String,
+    pub values: Vec<isize>,
+}
+
+impl AsciiOutput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn take_text(&mut self) -> String {
+        std::mem::take(&mut self.text)
+    }
+}
+
+impl IntcodeOutput for AsciiOutput {
+    fn write(&mut self, value: isize) {
+        if (0..=ASCII_MAX).contains(&value) {
+            self.text.push(value as u8 as char);
+        } else {
+            self.values.push(value);
+        }
+    }
+}
+
+/// Reads from stdin a line at a time. In ASCII mode the line is fed character by character
+/// (newline included); otherwise each whitespace-separated integer is one value, and words
+/// that aren't integers are reported on stderr and skipped.
+#[derive(Debug)]
+pub struct StdinInput {
+    ascii: bool,
+    pending: Stream,
+}
+
+impl StdinInput {
+    pub fn ascii() -> Self {
+        StdinInput {
+            ascii: true,
+            pending: Stream::new(),
+        }
+    }
+
+    pub fn numeric() -> Self {
+        StdinInput {
+            ascii: false,
+            pending: Stream::new(),
+        }
+    }
+
+    fn fill(&mut self) -> Option<()> {
+        while self.pending.is_empty() {
+            let mut line = String::new();
+            if io::stdin().lock().read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+
+            self.push_line(&line);
+        }
+        Some(())
+    }
+
+    fn push_line(&mut self, line: &str) {
+        if self.ascii {
+            let line = line.trim_end_matches(['\r', '\n']);
+            self.pending.extend(line.bytes().map(|b| b as isize));
+            self.pending.push_back('\n' as isize);
+        } else {
+            for word in line.split_whitespace() {
+                match word.parse::<isize>() {
+                    Ok(value) => self.pending.push_back(value),
+                    Err(e) => eprintln!("Ignoring input '{}': {}", word, e),
+                }
+            }
+        }
+    }
+}
+
+impl IntcodeInput for StdinInput {
+    fn read(&mut self) -> Option<isize> {
+        self.fill()?;
+        self.pending.pop_front()
+    }
+}
+
+/// Writes output to stdout. In ASCII mode characters are printed as text and anything else
+/// on a line of its own; otherwise every value is printed on its own line.
+#[derive(Debug)]
+pub struct StdoutOutput {
+    ascii: bool,
+}
+
+impl StdoutOutput {
+    pub fn ascii() -> Self {
+        StdoutOutput { ascii: true }
+    }
+
+    pub fn numeric() -> Self {
+        StdoutOutput { ascii: false }
+    }
+
+    fn print(&self, out: &mut impl Write, value: isize) -> io::Result<()> {
+        if self.ascii && (0..=ASCII_MAX).contains(&value) {
+            write!(out, "{}", value as u8 as char)
+        } else {
+            writeln!(out, "{}", value)
+        }
+    }
+}
+
+impl IntcodeOutput for StdoutOutput {
+    fn write(&mut self, value: isize) {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        let _ = self.print(&mut out, value);
+        let _ = out.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads two values and prints their sum, then their product.
+    fn sum_product() -> Tape {
+        parse_intcode_program("3,17,3,18,1,17,18,19,4,19,2,17,18,19,4,19,99,0,0,0")
+    }
+
+    #[test]
+    fn test_streams_and_vecs() {
+        let mut output = Vec::new();
+        let mut machine =
+            IntcodeMachine::new_io(sum_product(), Stream::from(vec![3, 4]), &mut output);
+        assert_eq!(machine.run(), Ok(StopStatus::Halted));
+        drop(machine);
+        assert_eq!(output, vec![7, 12]);
+
+        let mut machine =
+            IntcodeMachine::new_io(sum_product(), Stream::from(vec![5]), Stream::new());
+        assert_eq!(machine.run(), Ok(StopStatus::BlockedOnInput));
+        machine.input.push_back(6);
+        assert_eq!(machine.run(), Ok(StopStatus::Halted));
+        assert_eq!(machine.output, Stream::from(vec![11, 30]));
+    }
+
+    #[test]
+    fn test_boxed_ports() {
+        let input: Box<dyn IntcodeInput> = Box::new(input_iter(vec![2, 9]));
+        let mut machine = IntcodeMachine::new_io(sum_product(), input, Box::new(Vec::new()));
+        assert_eq!(machine.run(), Ok(StopStatus::Halted));
+        assert_eq!(machine.input.read(), None);
+        assert_eq!(*machine.output, vec![11, 18]);
+    }
+
+    #[test]
+    fn test_closures() {
+        let mut asked = 0;
+        let mut seen = Vec::new();
+        let input = input_fn(|| {
+            asked += 1;
+            Some(asked * 10)
+        });
+        let mut machine = IntcodeMachine::new_io(sum_product(), input, output_fn(|v| seen.push(v)));
+        assert_eq!(machine.run(), Ok(StopStatus::Halted));
+        drop(machine);
+        assert_eq!(asked, 2);
+        assert_eq!(seen, vec![30, 200]);
+
+        let mut machine = IntcodeMachine::new_io(sum_product(), input_fn(|| None), Vec::new());
+        assert_eq!(machine.run(), Ok(StopStatus::BlockedOnInput));
+        assert_eq!(machine.pc(), 0);
+    }
+
+    #[test]
+    fn test_iterator_blocks_when_exhausted() {
+        let mut machine = IntcodeMachine::new_io(sum_product(), input_iter(vec![1]), Vec::new());
+        assert_eq!(machine.run(), Ok(StopStatus::BlockedOnInput));
+        assert_eq!(machine.pc(), 2);
+        assert!(machine.output.is_empty());
+    }
+
+    #[test]
+    fn test_ascii_ports() {
+        let mut input = AsciiInput::new();
+        input.push_str("hi");
+        input.push_line("!");
+        let read: Vec<isize> = std::iter::from_fn(|| input.read()).collect();
+        assert_eq!(read, vec![104, 105, 33, 10]);
+
+        let mut output = AsciiOutput::new();
+        for &value in &[79, 75, 10, 128, -1, 127] {
+            output.write(value);
+        }
+        assert_eq!(output.take_text(), "OK\n\u{7f}");
+        assert_eq!(output.text, "");
+        assert_eq!(output.values, vec![128, -1]);
+    }
+
+    #[test]
+    fn test_stdin_lines() {
+        let mut ascii = StdinInput::ascii();
+        ascii.push_line("go 1\r\n");
+        ascii.push_line("\n");
+        assert_eq!(ascii.pending, Stream::from(vec![103, 111, 32, 49, 10, 10]));
+
+        let mut numeric = StdinInput::numeric();
+        numeric.push_line(" 12 -3\t4\n");
+        assert_eq!(numeric.pending, Stream::from(vec![12, -3, 4]));
+    }
+
+    #[test]
+    fn test_stdin_skips_words_that_are_not_integers() {
+        let mut numeric = StdinInput::numeric();
+        numeric.push_line("1 two 3 4.5 99999999999999999999 -6\n");
+        assert_eq!(numeric.pending, Stream::from(vec![1, 3, -6]));
+    }
+
+    #[test]
+    fn test_stdout_format() {
+        let print = |output: StdoutOutput, values: &[isize]| {
+            let mut out = Vec::new();
+            for &value in values {
+                output.print(&mut out, value).unwrap();
+            }
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(
+            print(StdoutOutput::ascii(), &[72, 105, 10, 4000]),
+            "Hi\n4000\n"
+        );
+        assert_eq!(print(StdoutOutput::numeric(), &[72, -1]), "72\n-1\n");
+    }
+}
@@ -0,0 +1,187 @@
+use super::disasm::{Line, LineKind, Listing};
+use super::*;
+use std::collections::HashMap;
+
+/// Hook that counts how often each address executes, how often each operation runs and
+/// how often each memory address is read and written, e.g.
+/// `IntcodeMachine::new(tape).with_hook(Profiler::new())`.
+#[derive(Debug, Default, Clone)]
+pub struct Profiler {
+    pub executions: HashMap<usize, usize>,
+    pub operations: HashMap<&'static str, usize>,
+    pub reads: HashMap<usize, usize>,
+    pub writes: HashMap<usize, usize>,
+    pub instructions: usize,
+}
+
+fn ranked<K: Copy + Ord>(counts: &HashMap<K, usize>, top: usize) -> Vec<(K, usize)> {
+    let mut ranked: Vec<(K, usize)> = counts.iter().map(|(k, n)| (*k, *n)).collect();
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    ranked.truncate(top);
+    ranked
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn percent(&self, count: usize) -> f64 {
+        100.0 * count as f64 / self.instructions.max(1) as f64
+    }
+
+    /// The `top` busiest instruction addresses, all operations, and the `top` most read and
+    /// most written addresses, each sorted by count.
+    pub fn report(&self, top: usize) -> String {
+        let mut out = format!(
+            "{} instructions executed at {} distinct addresses\n",
+            self.instructions,
+            self.executions.len()
+        );
+
+        out.push_str("\nhottest instructions:\n");
+        for (address, count) in ranked(&self.executions, top) {
+            out.push_str(&format!(
+                "  {:>6}  {:>12}  {:>5.1}%\n",
+                address,
+                count,
+                self.percent(count)
+            ));
+        }
+
+        out.push_str("\noperations:\n");
+        for (mnemonic, count) in ranked(&self.operations, self.operations.len()) {
+            out.push_str(&format!(
+                "  {:<6}  {:>12}  {:>5.1}%\n",
+                mnemonic,
+                count,
+                self.percent(count)
+            ));
+        }
+
+        for (title, counts) in &[("most read", &self.reads), ("most written", &self.writes)] {
+            out.push_str(&format!("\n{} addresses:\n", title));
+            for (address, count) in ranked(counts, top) {
+                out.push_str(&format!("  {:>6}  {:>12}\n", address, count));
+            }
+        }
+        out
+    }
+
+    /// The listing with execution counts next to code and read (`r`), write (`w`) and
+    /// execution (`x`) counts next to data. Code that never ran is marked with `-`.
+    pub fn annotate(&self, listing: &Listing) -> String {
+        let sum = |counts: &HashMap<usize, usize>, line: &Line| -> usize {
+            (line.address..line.address + line.words.len())
+                .filter_map(|a| counts.get(&a))
+                .sum()
+        };
+        listing.render_with(|line| {
+            let text = match line.kind {
+                LineKind::Code(_) => match self.executions.get(&line.address) {
+                    Some(count) => count.to_string(),
+                    None => "-".to_string(),
+                },
+                LineKind::Data => {
+                    // Code only reached through computed jumps shows up as data.
+                    let counts = [
+                        ('x', sum(&self.executions, line)),
+                        ('r', sum(&self.reads, line)),
+                        ('w', sum(&self.writes, line)),
+                    ];
+                    counts
+                        .iter()
+                        .filter(|(_, count)| *count > 0)
+                        .map(|(kind, count)| format!("{}{}", kind, count))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                }
+            };
+            format!("{:>16}", text)
+        })
+    }
+}
+
+impl IntcodeHook for Profiler {
+    fn on_instruction(&mut self, pc: isize, opcode: &Opcode) {
+        self.instructions += 1;
+        *self.executions.entry(pc as usize).or_insert(0) += 1;
+        *self
+            .operations
+            .entry(opcode.operation.mnemonic())
+            .or_insert(0) += 1;
+    }
+
+    fn on_read(&mut self, addr: usize, _value: isize) {
+        *self.reads.entry(addr).or_insert(0) += 1;
+    }
+
+    fn on_write(&mut self, addr: usize, _old: isize, _new: isize) {
+        *self.writes.entry(addr).or_insert(0) += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::disasm::disassemble;
+
+    // Counts [14] up to 3, keeping the loop condition in [15].
+    const COUNTER: &str = "1001,14,1,14,4,14,1007,14,3,15,1005,15,0,99,0,0";
+
+    fn profile() -> Profiler {
+        let mut machine =
+            IntcodeMachine::new(parse_intcode_program(COUNTER)).with_hook(Profiler::new());
+        assert_eq!(machine.run(), Ok(StopStatus::Halted));
+        machine.hook
+    }
+
+    #[test]
+    fn test_counts() {
+        let profiler = profile();
+        assert_eq!(profiler.instructions, 13);
+        assert_eq!(profiler.executions[&0], 3);
+        assert_eq!(profiler.executions[&13], 1);
+        assert_eq!(profiler.operations["ADD"], 3);
+        assert_eq!(profiler.operations["HLT"], 1);
+        assert_eq!(profiler.reads[&14], 9);
+        assert_eq!(profiler.reads[&15], 3);
+        assert_eq!(profiler.writes[&14], 3);
+        assert_eq!(profiler.writes[&15], 3);
+    }
+
+    #[test]
+    fn test_report() {
+        let report = profile().report(2);
+        assert!(report.starts_with("13 instructions executed at 5 distinct addresses\n"));
+        assert!(
+            report.contains(
+                "hottest instructions:\n       0             3   23.1%\n       4             3   23.1%\n\n"
+            ),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("  HLT                1    7.7%\n"),
+            "{}",
+            report
+        );
+        assert!(
+            report
+                .contains("most read addresses:\n      14             9\n      15             3\n"),
+            "{}",
+            report
+        );
+    }
+
+    #[test]
+    fn test_annotate() {
+        let text = profile().annotate(&disassemble(&parse_intcode_program(COUNTER)));
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0].trim(), "L0000:");
+        assert!(lines[1].starts_with("               3       0  1001,14,1,14"));
+        assert!(lines[5].starts_with("               1      13  99"));
+        assert!(lines[6].starts_with("          r12 w6      14"));
+        assert!(lines[6].ends_with(".data 0, 0"));
+    }
+}
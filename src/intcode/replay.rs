@@ -0,0 +1,385 @@
+//! Recording and replaying a machine's I/O. Attach a `Recorder` hook to capture every value
+//! consumed and produced together with when it happened, save the `Recording`, and later
+//! `replay` it against the same program to check it still behaves identically.
+//!
+//! Recordings are saved as text, one event per line: `in` or `out`, the instruction index and
+//! the value, e.g. `out 1523 72`. Blank lines and lines starting with `#` are ignored.
+
+use super::ports::IterInput;
+use super::*;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EventKind {
+    Input,
+    Output,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub kind: EventKind,
+    /// Index of the instruction that consumed or produced the value, counted from when
+    /// recording started.
+    pub instruction: usize,
+    pub value: isize,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            EventKind::Input => "in",
+            EventKind::Output => "out",
+        };
+        write!(f, "{} {} {}", kind, self.instruction, self.value)
+    }
+}
+
+impl FromStr for Event {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (kind, instruction, value) = match fields.as_slice() {
+            [kind, instruction, value] => (kind, instruction, value),
+            _ => return Err(format!("expected `in|out <instruction> <value>`: {}", line)),
+        };
+        let kind = match *kind {
+            "in" => EventKind::Input,
+            "out" => EventKind::Output,
+            _ => return Err(format!("unknown event kind `{}`", kind)),
+        };
+        Ok(Event {
+            kind,
+            instruction: instruction
+                .parse()
+                .map_err(|_| format!("bad instruction index `{}`", instruction))?,
+            value: value
+                .parse()
+                .map_err(|_| format!("bad value `{}`", value))?,
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Recording {
+    pub events: Vec<Event>,
+}
+
+impl Recording {
+    pub fn inputs(&self) -> impl Iterator<Item = isize> + '_ {
+        self.events
+            .iter()
+            .filter(|event| event.kind == EventKind::Input)
+            .map(|event| event.value)
+    }
+
+    pub fn outputs(&self) -> impl Iterator<Item = isize> + '_ {
+        self.events
+            .iter()
+            .filter(|event| event.kind == EventKind::Output)
+            .map(|event| event.value)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        std::fs::write(path, self.to_string())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        std::fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl fmt::Display for Recording {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# intcode recording: {} events", self.events.len())?;
+        for event in &self.events {
+            writeln!(f, "{}", event)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Recording {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let events = s
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(i, line)| line.parse().map_err(|e| format!("line {}: {}", i + 1, e)))
+            .collect::<Result<_, _>>()?;
+        Ok(Recording { events })
+    }
+}
+
+/// Hook that appends every input and output to `recording`.
+#[derive(Debug, Default, Clone)]
+pub struct Recorder {
+    pub recording: Recording,
+    instructions: usize,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(&mut self, kind: EventKind, value: isize) {
+        self.recording.events.push(Event {
+            kind,
+            // The current instruction was already counted in `on_instruction`.
+            instruction: self.instructions - 1,
+            value,
+        });
+    }
+}
+
+impl IntcodeHook for Recorder {
+    fn on_instruction(&mut self, _pc: isize, _opcode: &Opcode) {
+        self.instructions += 1;
+    }
+
+    fn on_input(&mut self, value: isize) {
+        self.push(EventKind::Input, value);
+    }
+
+    fn on_output(&mut self, value: isize) {
+        self.push(EventKind::Output, value);
+    }
+}
+
+/// Why a replay did not reproduce its recording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    Fault(IntcodeError),
+    /// Event `index` differs. `expected` is `None` if the machine went on past the end of
+    /// the recording, `actual` is `None` if it halted or ran out of input before reaching it.
+    Diverged {
+        index: usize,
+        expected: Option<Event>,
+        actual: Option<Event>,
+    },
+    /// The machine ran `instructions` instructions without halting or running out of input.
+    BudgetExhausted {
+        instructions: usize,
+    },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let describe = |event: &Option<Event>| match event {
+            Some(event) => event.to_string(),
+            None => "nothing".to_string(),
+        };
+        match self {
+            ReplayError::Fault(e) => write!(f, "machine fault during replay: {}", e),
+            ReplayError::Diverged {
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "replay diverged at event {}: expected {}, got {}",
+                index,
+                describe(expected),
+                describe(actual)
+            ),
+            ReplayError::BudgetExhausted { instructions } => write!(
+                f,
+                "replay still running after {} instructions",
+                instructions
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<IntcodeError> for ReplayError {
+    fn from(e: IntcodeError) -> Self {
+        ReplayError::Fault(e)
+    }
+}
+
+/// Compares events against a recording as they happen, remembering the first mismatch.
+struct Verifier<'a> {
+    expected: &'a [Event],
+    recorder: Recorder,
+    divergence: Option<ReplayError>,
+}
+
+impl<'a> Verifier<'a> {
+    fn check(&mut self) {
+        if self.divergence.is_some() {
+            return;
+        }
+        let index = self.recorder.recording.events.len() - 1;
+        let actual = self.recorder.recording.events[index];
+        let expected = self.expected.get(index).copied();
+        if expected != Some(actual) {
+            self.divergence = Some(ReplayError::Diverged {
+                index,
+                expected,
+                actual: Some(actual),
+            });
+        }
+    }
+}
+
+impl<'a> IntcodeHook for Verifier<'a> {
+    fn on_instruction(&mut self, pc: isize, opcode: &Opcode) {
+        self.recorder.on_instruction(pc, opcode);
+    }
+
+    fn on_input(&mut self, value: isize) {
+        self.recorder.on_input(value);
+        self.check();
+    }
+
+    fn on_output(&mut self, value: isize) {
+        self.recorder.on_output(value);
+        self.check();
+    }
+}
+
+/// Runs `tape` on the recorded inputs and checks that every input is consumed and every
+/// output produced at the same instruction as in `recording`. Stops at the first difference,
+/// or once the machine halts or runs out of input, giving up after `budget` instructions so
+/// a program that stops doing I/O without halting can't hang the replay. Returns the number
+/// of instructions run.
+pub fn replay(tape: Tape, recording: &Recording, budget: usize) -> Result<usize, ReplayError> {
+    let inputs: Vec<isize> = recording.inputs().collect();
+    let verifier = Verifier {
+        expected: &recording.events,
+        recorder: Recorder::new(),
+        divergence: None,
+    };
+    let mut machine =
+        IntcodeMachine::new_io(tape, IterInput(inputs.into_iter()), Vec::new()).with_hook(verifier);
+
+    let mut stopped = false;
+    while !stopped && machine.instruction_count() < budget {
+        let status = machine.tick()?;
+        if let Some(divergence) = machine.hook.divergence.take() {
+            return Err(divergence);
+        }
+        stopped = matches!(
+            status,
+            Some(StopStatus::Halted) | Some(StopStatus::BlockedOnInput)
+        );
+    }
+    if !stopped {
+        return Err(ReplayError::BudgetExhausted {
+            instructions: machine.instruction_count(),
+        });
+    }
+
+    let index = machine.hook.recorder.recording.events.len();
+    if index < recording.events.len() {
+        return Err(ReplayError::Diverged {
+            index,
+            expected: Some(recording.events[index]),
+            actual: None,
+        });
+    }
+    Ok(machine.instruction_count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Echoes inputs doubled until it reads 0.
+    const DOUBLER: &str = "3,20,1006,20,14,1002,20,2,20,4,20,1105,1,0,99";
+
+    fn record(program: &str, inputs: &[isize]) -> Recording {
+        let mut machine =
+            IntcodeMachine::new(parse_intcode_program(program)).with_hook(Recorder::new());
+        machine.input.borrow_mut().extend(inputs);
+        machine.run().unwrap();
+        machine.hook.recording
+    }
+
+    #[test]
+    fn test_record() {
+        let recording = record(DOUBLER, &[3, 0]);
+        let event = |kind, instruction, value| Event {
+            kind,
+            instruction,
+            value,
+        };
+        assert_eq!(
+            recording.events,
+            vec![
+                event(EventKind::Input, 0, 3),
+                event(EventKind::Output, 3, 6),
+                event(EventKind::Input, 5, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let recording = record(DOUBLER, &[3, -4, 0]);
+        let text = recording.to_string();
+        assert!(text.starts_with("# intcode recording: 5 events\nin 0 3\nout 3 6\n"));
+        assert_eq!(text.parse::<Recording>(), Ok(recording));
+        assert_eq!(
+            "in 1 2\nsideways 1 2".parse::<Recording>(),
+            Err("line 2: unknown event kind `sideways`".to_string())
+        );
+    }
+
+    #[test]
+    fn test_replay() {
+        let recording = record(DOUBLER, &[3, 5, 0]);
+        let tape = parse_intcode_program(DOUBLER);
+        assert_eq!(replay(tape, &recording, 1000), Ok(12));
+    }
+
+    #[test]
+    fn test_replay_diverges() {
+        let recording = record(DOUBLER, &[3, 5, 0]);
+        // Triples instead of doubling.
+        let tripler = parse_intcode_program(&DOUBLER.replace("1002,20,2", "1002,20,3"));
+        assert_eq!(
+            replay(tripler, &recording, 1000),
+            Err(ReplayError::Diverged {
+                index: 1,
+                expected: Some(recording.events[1]),
+                actual: Some(Event {
+                    kind: EventKind::Output,
+                    instruction: 3,
+                    value: 9,
+                }),
+            })
+        );
+
+        // Halts straight away.
+        assert_eq!(
+            replay(vec![99], &recording, 1000),
+            Err(ReplayError::Diverged {
+                index: 0,
+                expected: Some(recording.events[0]),
+                actual: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_replay_gives_up_on_silent_loops() {
+        let recording = record(DOUBLER, &[3, 0]);
+        // Spins where it should halt.
+        let spinner = parse_intcode_program(&DOUBLER.replace(",99", ",1105,1,14"));
+        assert_eq!(
+            replay(spinner, &recording, 1000),
+            Err(ReplayError::BudgetExhausted { instructions: 1000 })
+        );
+    }
+}
@@ -0,0 +1,651 @@
+//! Concolic execution: a program runs concretely while a hook tracks how every value derived
+//! from the symbolic inputs and tape cells depends on them. Each run leaves behind the path
+//! constraints of the branches it took; the solver then either adjusts the inputs to hit the
+//! goal along the same path or flips a branch to explore a new one.
+//!
+//! Sums, products with a constant and comparisons are tracked exactly. Anything else, like
+//! the product of two symbolic values or a symbolic address, is pinned to its concrete value
+//! by an extra constraint, added lazily so that a value which is never used doesn't
+//! constrain anything. Every solution is confirmed by a concrete run before it is returned.
+
+pub mod solver;
+
+use self::solver::{solve, Constraint, Linear, Relation, Var};
+use super::ports::IterInput;
+use super::*;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::ops::RangeInclusive;
+
+/// What a search is looking for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Goal {
+    /// The `index`-th output, counting from 0, is `value`.
+    Output { index: usize, value: isize },
+    /// The program halts with `value` at `addr`.
+    Memory { addr: usize, value: isize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Solution {
+    /// Values of the symbolic cells, in the order they were declared.
+    pub cells: Vec<(usize, isize)>,
+    /// Inputs consumed by the run that reached the goal.
+    pub inputs: Vec<isize>,
+    /// Number of concrete runs the search took.
+    pub runs: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Term {
+    Linear(Linear),
+    /// 1 if the constraint holds, 0 otherwise.
+    Condition(Constraint),
+}
+
+/// How a value depends on the symbolic variables, along with the constraints that must hold
+/// for that description to be accurate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Value {
+    term: Term,
+    assumptions: Vec<Constraint>,
+}
+
+impl Value {
+    fn constant(value: isize) -> Self {
+        Value {
+            term: Term::Linear(Linear::constant(value)),
+            assumptions: Vec::new(),
+        }
+    }
+
+    fn var(var: Var) -> Self {
+        Value {
+            term: Term::Linear(Linear::var(var)),
+            assumptions: Vec::new(),
+        }
+    }
+
+    fn lift(value: Option<&Value>, concrete: isize) -> Value {
+        value.cloned().unwrap_or_else(|| Value::constant(concrete))
+    }
+
+    /// `None` if the value is just a constant with nothing attached.
+    fn lower(self) -> Option<Value> {
+        match &self.term {
+            Term::Linear(linear) if linear.terms.is_empty() && self.assumptions.is_empty() => None,
+            _ => Some(self),
+        }
+    }
+
+    /// The value as a linear expression. A condition turns into its concrete 0 or 1, assuming
+    /// it keeps the same truth value.
+    fn linear(&self, concrete: isize) -> (Linear, Vec<Constraint>) {
+        let mut assumptions = self.assumptions.clone();
+        let linear = match &self.term {
+            Term::Linear(linear) => linear.clone(),
+            Term::Condition(condition) => {
+                assumptions.push(truth(condition, concrete != 0));
+                Linear::constant(concrete)
+            }
+        };
+        (linear, assumptions)
+    }
+
+    /// Constraints under which the value is exactly `concrete`.
+    fn pinned(&self, concrete: isize) -> Vec<Constraint> {
+        let (linear, mut assumptions) = self.linear(concrete);
+        assumptions.extend(Constraint::equals(&linear, concrete));
+        assumptions
+    }
+
+    /// Constraints under which a value that is currently `concrete` becomes `target`.
+    fn pinned_to(&self, concrete: isize, target: isize) -> Vec<Constraint> {
+        match &self.term {
+            Term::Condition(condition) => {
+                let mut constraints = self.assumptions.clone();
+                match target {
+                    0 | 1 => constraints.push(truth(condition, target == 1)),
+                    // A comparison can't produce anything else.
+                    _ => constraints.push(Constraint::new(Linear::constant(1), Relation::Eq)),
+                }
+                constraints
+            }
+            Term::Linear(_) => {
+                let (linear, mut constraints) = self.linear(concrete);
+                match Constraint::equals(&linear, target) {
+                    Some(constraint) => constraints.push(constraint),
+                    None => constraints.push(Constraint::new(Linear::constant(1), Relation::Eq)),
+                }
+                constraints
+            }
+        }
+    }
+
+    /// Constraints under which the value is nonzero, or zero if `nonzero` is false.
+    fn branch(&self, nonzero: bool) -> Vec<Constraint> {
+        let mut constraints = self.assumptions.clone();
+        let condition = match &self.term {
+            Term::Linear(linear) => Constraint::new(linear.clone(), Relation::Ne),
+            Term::Condition(condition) => condition.clone(),
+        };
+        constraints.push(truth(&condition, nonzero));
+        constraints
+    }
+}
+
+fn truth(condition: &Constraint, holds: bool) -> Constraint {
+    if holds {
+        condition.clone()
+    } else {
+        condition.negate()
+    }
+}
+
+/// Operand of a binary operation: its concrete value and how it depends on the variables.
+type Arg = (isize, Value);
+
+/// Applies `op` to the linear forms of `a` and `b`. If that fails the result is the concrete
+/// `result`, valid as long as both operands keep their current values.
+fn combine(
+    a: &Arg,
+    b: &Arg,
+    result: isize,
+    op: impl Fn(&Linear, &Linear) -> Option<Term>,
+) -> Value {
+    let (la, sa) = a.1.linear(a.0);
+    let (lb, sb) = b.1.linear(b.0);
+    let mut assumptions = sa;
+    assumptions.extend(sb);
+    match op(&la, &lb) {
+        Some(term) => Value { term, assumptions },
+        None => {
+            assumptions.extend(a.1.pinned(a.0));
+            assumptions.extend(b.1.pinned(b.0));
+            Value {
+                term: Term::Linear(Linear::constant(result)),
+                assumptions,
+            }
+        }
+    }
+}
+
+fn compare(la: &Linear, lb: &Linear, relation: Relation, result: isize) -> Option<Term> {
+    let difference = la.checked_sub(lb)?;
+    if difference.terms.is_empty() {
+        return Some(Term::Linear(Linear::constant(result)));
+    }
+    Some(Term::Condition(Constraint::new(difference, relation)))
+}
+
+/// Side effects of the instruction being executed, gathered from the hook callbacks.
+#[derive(Debug)]
+struct Pending {
+    pc: isize,
+    opcode: Opcode,
+    reads: Vec<(usize, isize)>,
+    write: Option<(usize, isize)>,
+}
+
+/// Hook following values through the machine. The symbolic view of an instruction is worked
+/// out once it has completed, when the next one starts.
+#[derive(Debug)]
+struct Tracker {
+    shadow: HashMap<usize, Value>,
+    bp: isize,
+    bp_value: Option<Value>,
+    first_input: Var,
+    inputs: Vec<isize>,
+    path: Vec<Constraint>,
+    on_path: HashSet<Constraint>,
+    /// Symbolic view of each output and the length of the path when it was produced.
+    outputs: Vec<(Option<Value>, usize)>,
+    pending: Option<Pending>,
+}
+
+impl Tracker {
+    fn new(cells: &[usize]) -> Self {
+        Tracker {
+            shadow: cells
+                .iter()
+                .enumerate()
+                .map(|(var, addr)| (*addr, Value::var(var)))
+                .collect(),
+            bp: 0,
+            bp_value: None,
+            first_input: cells.len(),
+            inputs: Vec::new(),
+            path: Vec::new(),
+            on_path: HashSet::new(),
+            outputs: Vec::new(),
+            pending: None,
+        }
+    }
+
+    fn require(&mut self, constraints: Vec<Constraint>) {
+        for constraint in constraints {
+            if !constraint.is_constant() && self.on_path.insert(constraint.clone()) {
+                self.path.push(constraint);
+            }
+        }
+    }
+
+    /// Concrete value and symbolic view of operand `index`, consuming its read if it has one.
+    fn operand(
+        &mut self,
+        pending: &Pending,
+        index: usize,
+        reads: &mut impl Iterator<Item = (usize, isize)>,
+    ) -> (isize, Value) {
+        let operand = pending.opcode.operands[index];
+        let word = self.shadow.get(&(pending.pc as usize + 1 + index)).cloned();
+        if operand.mode == AddressingMode::Immediate {
+            return (operand.value, Value::lift(word.as_ref(), operand.value));
+        }
+
+        let (addr, value) = reads.next().unwrap_or((0, 0));
+        let mut result = Value::lift(self.shadow.get(&addr), value);
+        if let Some(address) = self.address(operand, word) {
+            // The value came from wherever the address happened to point.
+            let (linear, mut assumptions) = result.linear(value);
+            assumptions.extend(address.pinned(addr as isize));
+            result = Value {
+                term: Term::Linear(linear),
+                assumptions,
+            };
+        }
+        (value, result)
+    }
+
+    /// Symbolic view of a position or relative operand's address, if it has one.
+    fn address(&self, operand: Operand, word: Option<Value>) -> Option<Value> {
+        match operand.mode {
+            AddressingMode::Immediate => None,
+            AddressingMode::AbsoluteAddress => word,
+            AddressingMode::BasePointerRelative => {
+                if word.is_none() && self.bp_value.is_none() {
+                    return None;
+                }
+                let bp = (self.bp, Value::lift(self.bp_value.as_ref(), self.bp));
+                let offset = (operand.value, Value::lift(word.as_ref(), operand.value));
+                let addr = self.bp + operand.value;
+                combine(&bp, &offset, addr, |a, b| {
+                    a.checked_add(b).map(Term::Linear)
+                })
+                .lower()
+            }
+        }
+    }
+
+    fn store(&mut self, pending: &Pending, value: Option<Value>) {
+        let (addr, _) = match pending.write {
+            Some(write) => write,
+            None => return,
+        };
+        let index = pending.opcode.operands.len() - 1;
+        let operand = pending.opcode.operands[index];
+        let word = self.shadow.get(&(pending.pc as usize + 1 + index)).cloned();
+        // Writing elsewhere would change what the rest of the run sees.
+        if let Some(address) = self.address(operand, word) {
+            self.require(address.pinned(addr as isize));
+        }
+        match value {
+            Some(value) => self.shadow.insert(addr, value),
+            None => self.shadow.remove(&addr),
+        };
+    }
+
+    fn finish_instruction(&mut self) {
+        let mut pending = match self.pending.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+        if let Some(word) = self.shadow.get(&(pending.pc as usize)).cloned() {
+            self.require(word.pinned(pending.opcode.encode()[0]));
+        }
+
+        let mut reads = std::mem::take(&mut pending.reads).into_iter();
+        let result = pending.write.map_or(0, |(_, value)| value);
+        match pending.opcode.operation {
+            Operation::Add | Operation::Multiply | Operation::LessThan | Operation::Equals => {
+                let a = self.operand(&pending, 0, &mut reads);
+                let b = self.operand(&pending, 1, &mut reads);
+                let value = match pending.opcode.operation {
+                    Operation::Add => {
+                        combine(&a, &b, result, |x, y| x.checked_add(y).map(Term::Linear))
+                    }
+                    Operation::Multiply => combine(&a, &b, result, |x, y| {
+                        match (x.as_constant(), y.as_constant()) {
+                            (Some(k), _) => y.checked_scale(k),
+                            (_, Some(k)) => x.checked_scale(k),
+                            _ => None,
+                        }
+                        .map(Term::Linear)
+                    }),
+                    Operation::LessThan => {
+                        combine(&a, &b, result, |x, y| compare(x, y, Relation::Lt, result))
+                    }
+                    _ => combine(&a, &b, result, |x, y| compare(x, y, Relation::Eq, result)),
+                };
+                self.store(&pending, value.lower());
+            }
+            Operation::Input => {
+                let var = self.first_input + self.inputs.len() - 1;
+                self.store(&pending, Some(Value::var(var)));
+            }
+            Operation::Output => {
+                let (_, symbolic) = self.operand(&pending, 0, &mut reads);
+                self.outputs.push((symbolic.lower(), self.path.len()));
+            }
+            Operation::JumpTrue | Operation::JumpFalse => {
+                let (condition, condition_value) = self.operand(&pending, 0, &mut reads);
+                let (target, target_value) = self.operand(&pending, 1, &mut reads);
+                self.require(condition_value.branch(condition != 0));
+                let taken = (condition != 0) == (pending.opcode.operation == Operation::JumpTrue);
+                if taken {
+                    self.require(target_value.pinned(target));
+                }
+            }
+            Operation::AdjustBasePointer => {
+                let offset = self.operand(&pending, 0, &mut reads);
+                let bp = (self.bp, Value::lift(self.bp_value.as_ref(), self.bp));
+                let new_bp = self.bp + offset.0;
+                self.bp_value = combine(&bp, &offset, new_bp, |x, y| {
+                    x.checked_add(y).map(Term::Linear)
+                })
+                .lower();
+                self.bp = new_bp;
+            }
+            Operation::Halt => (),
+            Operation::Extended(op) => {
+                // Handlers are opaque, so whatever they read has to keep its value.
+                for index in 0..op.operand_count as usize - op.stores_result as usize {
+                    let (value, symbolic) = self.operand(&pending, index, &mut reads);
+                    self.require(symbolic.pinned(value));
+                }
+                self.store(&pending, None);
+            }
+        }
+    }
+}
+
+impl IntcodeHook for Tracker {
+    fn on_instruction(&mut self, pc: isize, opcode: &Opcode) {
+        self.finish_instruction();
+        self.pending = Some(Pending {
+            pc,
+            opcode: *opcode,
+            reads: Vec::new(),
+            write: None,
+        });
+    }
+
+    fn on_read(&mut self, addr: usize, value: isize) {
+        if let Some(pending) = &mut self.pending {
+            pending.reads.push((addr, value));
+        }
+    }
+
+    fn on_write(&mut self, addr: usize, _old: isize, new: isize) {
+        if let Some(pending) = &mut self.pending {
+            pending.write = Some((addr, new));
+        }
+    }
+
+    fn on_input(&mut self, value: isize) {
+        self.inputs.push(value);
+    }
+}
+
+/// Outcome of one concrete run.
+struct Run {
+    reached: bool,
+    inputs: Vec<isize>,
+    path: Vec<Constraint>,
+    /// Path prefix and extra constraints under which the goal would be met, if the goal
+    /// value depends on the variables at all.
+    goal: Option<(usize, Vec<Constraint>)>,
+}
+
+/// Searches for values of chosen tape cells and of the input stream that make a program
+/// reach a `Goal`, e.g. for day 2:
+///
+/// ```ignore
+/// let mut search = SymbolicSearch::new(tape);
+/// search.symbolic_cell(1, 0..=99).symbolic_cell(2, 0..=99);
+/// let solution = search.solve(Goal::Memory { addr: 0, value: 19690720 });
+/// ```
+#[derive(Debug, Clone)]
+pub struct SymbolicSearch {
+    tape: Tape,
+    cells: Vec<(usize, RangeInclusive<isize>)>,
+    input_domain: RangeInclusive<isize>,
+    max_runs: usize,
+    max_instructions: usize,
+    max_solver_nodes: usize,
+}
+
+impl SymbolicSearch {
+    pub fn new(tape: Tape) -> Self {
+        SymbolicSearch {
+            tape,
+            cells: Vec::new(),
+            input_domain: -1_000_000..=1_000_000,
+            max_runs: 1000,
+            max_instructions: 10_000_000,
+            max_solver_nodes: 10_000,
+        }
+    }
+
+    /// Makes the cell at `addr` a variable ranging over `domain`.
+    pub fn symbolic_cell(&mut self, addr: usize, domain: RangeInclusive<isize>) -> &mut Self {
+        assert!(!domain.is_empty(), "empty domain for cell {}", addr);
+        self.cells.push((addr, domain));
+        self
+    }
+
+    /// Range of every value read from the input stream, e.g. `0..=127` for ASCII programs.
+    pub fn input_domain(&mut self, domain: RangeInclusive<isize>) -> &mut Self {
+        assert!(!domain.is_empty(), "empty input domain");
+        self.input_domain = domain;
+        self
+    }
+
+    /// Limits on the number of concrete runs, the instructions per run, and the solver's
+    /// search nodes per query.
+    pub fn limits(&mut self, runs: usize, instructions: usize, solver_nodes: usize) -> &mut Self {
+        self.max_runs = runs;
+        self.max_instructions = instructions;
+        self.max_solver_nodes = solver_nodes;
+        self
+    }
+
+    fn domains(&self, inputs: usize) -> Vec<RangeInclusive<isize>> {
+        self.cells
+            .iter()
+            .map(|(_, domain)| domain.clone())
+            .chain(std::iter::repeat_n(self.input_domain.clone(), inputs))
+            .collect()
+    }
+
+    fn clamp(domain: &RangeInclusive<isize>, value: isize) -> isize {
+        value.max(*domain.start()).min(*domain.end())
+    }
+
+    fn run(&self, assignment: &[isize], goal: Goal) -> Run {
+        let mut tape = self.tape.clone();
+        for ((addr, _), value) in self.cells.iter().zip(assignment) {
+            if *addr >= tape.len() {
+                tape.resize(addr + 1, 0);
+            }
+            tape[*addr] = *value;
+        }
+        let default_input = Self::clamp(&self.input_domain, 0);
+        let inputs = assignment
+            .get(self.cells.len()..)
+            .unwrap_or(&[])
+            .to_vec()
+            .into_iter()
+            .chain(std::iter::repeat(default_input));
+        let cells: Vec<usize> = self.cells.iter().map(|(addr, _)| *addr).collect();
+        let mut machine = IntcodeMachine::new_io(tape, IterInput(inputs), Vec::new())
+            .with_hook(Tracker::new(&cells));
+
+        let status = machine.run_for(self.max_instructions);
+        // A faulting instruction has no effects worth tracking.
+        if status.is_ok() {
+            machine.hook.finish_instruction();
+        }
+        let halted = matches!(status, Ok(StopStatus::Halted));
+
+        let tracker = &machine.hook;
+        let (reached, goal) = match goal {
+            Goal::Output { index, value } => match machine.output.get(index) {
+                Some(&output) => {
+                    // An output followed by a fault is never tracked, so it can't be steered.
+                    let goal = tracker
+                        .outputs
+                        .get(index)
+                        .and_then(|(symbolic, prefix)| Some((*prefix, symbolic.as_ref()?)))
+                        .map(|(prefix, symbolic)| (prefix, symbolic.pinned_to(output, value)));
+                    (output == value, goal)
+                }
+                None => (false, None),
+            },
+            Goal::Memory { addr, value } => {
+                let current = machine.memory().get(addr);
+                let goal = tracker
+                    .shadow
+                    .get(&addr)
+                    .filter(|_| halted)
+                    .map(|symbolic| (tracker.path.len(), symbolic.pinned_to(current, value)));
+                (halted && current == value, goal)
+            }
+        };
+        Run {
+            reached,
+            inputs: tracker.inputs.clone(),
+            path: tracker.path.clone(),
+            goal,
+        }
+    }
+
+    /// Runs the search, returning the first assignment confirmed to reach `goal`. `None` if
+    /// none was found within the limits.
+    pub fn solve(&self, goal: Goal) -> Option<Solution> {
+        let seed: Vec<isize> = self
+            .cells
+            .iter()
+            .map(|(addr, domain)| Self::clamp(domain, self.tape.get(*addr).copied().unwrap_or(0)))
+            .collect();
+        // Assignments to try, each with the index of the first branch its run may flip.
+        let mut queue = VecDeque::from(vec![(seed, 0)]);
+        let mut flipped = HashSet::new();
+
+        for runs in 1..=self.max_runs {
+            let (assignment, bound) = queue.pop_front()?;
+            let run = self.run(&assignment, goal);
+            if run.reached {
+                return Some(Solution {
+                    cells: self
+                        .cells
+                        .iter()
+                        .map(|(addr, _)| *addr)
+                        .zip(assignment.iter().copied())
+                        .collect(),
+                    inputs: run.inputs,
+                    runs,
+                });
+            }
+
+            let domains = self.domains(run.inputs.len());
+            let hint: Vec<isize> = assignment
+                .iter()
+                .copied()
+                .take(self.cells.len())
+                .chain(run.inputs.iter().copied())
+                .collect();
+            let query = |constraints: &[Constraint]| {
+                solve(constraints, &domains, &hint, self.max_solver_nodes)
+            };
+
+            // Try to meet the goal along the path just taken.
+            if let Some((prefix, constraints)) = &run.goal {
+                let mut constraints = constraints.clone();
+                constraints.extend_from_slice(&run.path[..*prefix]);
+                if let Some(next) = query(&constraints) {
+                    queue.push_front((next, bound));
+                    continue;
+                }
+            }
+
+            // Otherwise explore paths that branch off this one. The same branch under the
+            // same prefix is only ever flipped once.
+            let mut prefix = DefaultHasher::new();
+            for (i, constraint) in run.path.iter().enumerate() {
+                let mut key = prefix.clone();
+                constraint.hash(&mut key);
+                if i >= bound && flipped.insert(key.finish()) {
+                    let mut constraints = run.path[..i].to_vec();
+                    constraints.push(constraint.negate());
+                    if let Some(next) = query(&constraints) {
+                        queue.push_back((next, i + 1));
+                    }
+                }
+                constraint.hash(&mut prefix);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_from_input() {
+        // Prints its input plus 5.
+        let search = SymbolicSearch::new(vec![3, 9, 1001, 9, 5, 9, 4, 9, 99, 0]);
+        let solution = search
+            .solve(Goal::Output {
+                index: 0,
+                value: 12,
+            })
+            .unwrap();
+        assert_eq!(solution.inputs, vec![7]);
+        assert!(solution.cells.is_empty());
+    }
+
+    #[test]
+    fn test_memory_from_cells() {
+        let mut search = SymbolicSearch::new(vec![1101, 0, 0, 0, 99]);
+        search.symbolic_cell(1, 0..=99).symbolic_cell(2, 0..=99);
+        let solution = search
+            .solve(Goal::Memory {
+                addr: 0,
+                value: 150,
+            })
+            .unwrap();
+        let (noun, verb) = (solution.cells[0].1, solution.cells[1].1);
+        assert_eq!(noun + verb, 150);
+        assert!((0..=99).contains(&noun) && (0..=99).contains(&verb));
+    }
+
+    #[test]
+    fn test_output_followed_by_fault() {
+        let search = SymbolicSearch::new(vec![104, 5, 0]);
+        assert_eq!(search.solve(Goal::Output { index: 0, value: 7 }), None);
+        assert!(search.solve(Goal::Output { index: 0, value: 5 }).is_some());
+    }
+
+    #[test]
+    fn test_unreachable_goal() {
+        let mut search = SymbolicSearch::new(vec![3, 7, 1002, 7, 2, 7, 99, 0]);
+        search.input_domain(0..=10).limits(50, 1000, 1000);
+        assert_eq!(search.solve(Goal::Memory { addr: 7, value: 7 }), None);
+        let solution = search.solve(Goal::Memory { addr: 7, value: 8 }).unwrap();
+        assert_eq!(solution.inputs, vec![4]);
+    }
+}
@@ -0,0 +1,393 @@
+//! Linear integer constraints over bounded variables and a small solver for them: bounds
+//! propagation, plus branching on variable domains where propagation alone doesn't decide.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+pub type Var = usize;
+
+/// `constant + sum(coefficient * var)`. Zero coefficients are never stored.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Linear {
+    pub constant: isize,
+    pub terms: BTreeMap<Var, isize>,
+}
+
+impl Linear {
+    pub fn constant(value: isize) -> Self {
+        Linear {
+            constant: value,
+            terms: BTreeMap::new(),
+        }
+    }
+
+    pub fn var(var: Var) -> Self {
+        let mut terms = BTreeMap::new();
+        terms.insert(var, 1);
+        Linear { constant: 0, terms }
+    }
+
+    pub fn as_constant(&self) -> Option<isize> {
+        if self.terms.is_empty() {
+            Some(self.constant)
+        } else {
+            None
+        }
+    }
+
+    /// `None` on overflow, here and in the other arithmetic methods.
+    pub fn checked_add(&self, other: &Linear) -> Option<Linear> {
+        let mut sum = self.clone();
+        sum.constant = sum.constant.checked_add(other.constant)?;
+        for (var, coefficient) in &other.terms {
+            let entry = sum.terms.entry(*var).or_insert(0);
+            *entry = entry.checked_add(*coefficient)?;
+            if *entry == 0 {
+                sum.terms.remove(var);
+            }
+        }
+        Some(sum)
+    }
+
+    pub fn checked_sub(&self, other: &Linear) -> Option<Linear> {
+        self.checked_add(&other.checked_scale(-1)?)
+    }
+
+    pub fn checked_scale(&self, factor: isize) -> Option<Linear> {
+        if factor == 0 {
+            return Some(Linear::default());
+        }
+        let mut terms = BTreeMap::new();
+        for (var, coefficient) in &self.terms {
+            terms.insert(*var, coefficient.checked_mul(factor)?);
+        }
+        Some(Linear {
+            constant: self.constant.checked_mul(factor)?,
+            terms,
+        })
+    }
+
+    /// Value under `assignment`, indexed by variable.
+    pub fn eval(&self, assignment: &[isize]) -> i128 {
+        self.terms
+            .iter()
+            .map(|(var, coefficient)| *coefficient as i128 * assignment[*var] as i128)
+            .sum::<i128>()
+            + self.constant as i128
+    }
+}
+
+impl fmt::Display for Linear {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for (var, coefficient) in &self.terms {
+            let sign = match (first, *coefficient < 0) {
+                (true, true) => "-",
+                (true, false) => "",
+                (false, true) => " - ",
+                (false, false) => " + ",
+            };
+            match coefficient.abs() {
+                1 => write!(f, "{}v{}", sign, var)?,
+                abs => write!(f, "{}{}*v{}", sign, abs, var)?,
+            }
+            first = false;
+        }
+        match (first, self.constant) {
+            (true, constant) => write!(f, "{}", constant),
+            (false, 0) => Ok(()),
+            (false, constant) if constant < 0 => write!(f, " - {}", -(constant as i128)),
+            (false, constant) => write!(f, " + {}", constant),
+        }
+    }
+}
+
+/// How a constraint's expression compares to zero.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Relation {
+    Eq,
+    Ne,
+    Lt,
+    Ge,
+}
+
+impl Relation {
+    pub fn negate(self) -> Relation {
+        match self {
+            Relation::Eq => Relation::Ne,
+            Relation::Ne => Relation::Eq,
+            Relation::Lt => Relation::Ge,
+            Relation::Ge => Relation::Lt,
+        }
+    }
+}
+
+/// `expr <relation> 0`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Constraint {
+    pub expr: Linear,
+    pub relation: Relation,
+}
+
+impl Constraint {
+    pub fn new(expr: Linear, relation: Relation) -> Self {
+        Constraint { expr, relation }
+    }
+
+    /// `expr == value`, or `None` if that can't be represented without overflow.
+    pub fn equals(expr: &Linear, value: isize) -> Option<Self> {
+        let expr = expr.checked_sub(&Linear::constant(value))?;
+        Some(Constraint::new(expr, Relation::Eq))
+    }
+
+    pub fn negate(&self) -> Self {
+        Constraint::new(self.expr.clone(), self.relation.negate())
+    }
+
+    /// Whether the constraint mentions no variables, so it holds or fails regardless.
+    pub fn is_constant(&self) -> bool {
+        self.expr.terms.is_empty()
+    }
+
+    pub fn holds(&self, assignment: &[isize]) -> bool {
+        let value = self.expr.eval(assignment);
+        match self.relation {
+            Relation::Eq => value == 0,
+            Relation::Ne => value != 0,
+            Relation::Lt => value < 0,
+            Relation::Ge => value >= 0,
+        }
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let relation = match self.relation {
+            Relation::Eq => "==",
+            Relation::Ne => "!=",
+            Relation::Lt => "<",
+            Relation::Ge => ">=",
+        };
+        write!(f, "{} {} 0", self.expr, relation)
+    }
+}
+
+fn floor_div(a: i128, b: i128) -> i128 {
+    let quotient = a / b;
+    if a % b != 0 && (a < 0) != (b < 0) {
+        quotient - 1
+    } else {
+        quotient
+    }
+}
+
+fn ceil_div(a: i128, b: i128) -> i128 {
+    -floor_div(-a, b)
+}
+
+/// Propagation rounds per search node. Chains like `x < y, y < x` over wide domains would
+/// otherwise shrink one step per round; branching settles those faster.
+const MAX_ROUNDS: usize = 64;
+
+struct Search<'a> {
+    constraints: &'a [Constraint],
+    hint: &'a [isize],
+    nodes: usize,
+    max_nodes: usize,
+}
+
+impl<'a> Search<'a> {
+    /// Narrows the domains to values that can still satisfy every constraint. Returns false
+    /// if some domain became empty.
+    fn propagate(&self, lo: &mut [i128], hi: &mut [i128]) -> bool {
+        for _ in 0..MAX_ROUNDS {
+            let mut changed = false;
+            for constraint in self.constraints {
+                let terms = &constraint.expr.terms;
+                let (mut min, mut max) = (
+                    constraint.expr.constant as i128,
+                    constraint.expr.constant as i128,
+                );
+                for (&var, &coefficient) in terms {
+                    let a = coefficient as i128;
+                    if a > 0 {
+                        min += a * lo[var];
+                        max += a * hi[var];
+                    } else {
+                        min += a * hi[var];
+                        max += a * lo[var];
+                    }
+                }
+
+                // Bounds on the whole expression, if the relation implies any.
+                let (lower, upper) = match constraint.relation {
+                    Relation::Eq => (Some(0), Some(0)),
+                    Relation::Lt => (None, Some(-1)),
+                    Relation::Ge => (Some(0), None),
+                    Relation::Ne => {
+                        if min == max && min == 0 {
+                            return false;
+                        }
+                        changed |= Self::exclude_root(constraint, lo, hi);
+                        if terms.keys().any(|&var| lo[var] > hi[var]) {
+                            return false;
+                        }
+                        continue;
+                    }
+                };
+                if lower.is_some_and(|l| max < l) || upper.is_some_and(|u| min > u) {
+                    return false;
+                }
+
+                for (&var, &coefficient) in terms {
+                    let a = coefficient as i128;
+                    let (own_min, own_max) = if a > 0 {
+                        (a * lo[var], a * hi[var])
+                    } else {
+                        (a * hi[var], a * lo[var])
+                    };
+                    // a * var <= upper - (min of the rest), a * var >= lower - (max of the rest)
+                    if let Some(upper) = upper {
+                        let bound = upper - (min - own_min);
+                        if a > 0 {
+                            let new_hi = floor_div(bound, a);
+                            if new_hi < hi[var] {
+                                hi[var] = new_hi;
+                                changed = true;
+                            }
+                        } else {
+                            let new_lo = ceil_div(bound, a);
+                            if new_lo > lo[var] {
+                                lo[var] = new_lo;
+                                changed = true;
+                            }
+                        }
+                    }
+                    if let Some(lower) = lower {
+                        let bound = lower - (max - own_max);
+                        if a > 0 {
+                            let new_lo = ceil_div(bound, a);
+                            if new_lo > lo[var] {
+                                lo[var] = new_lo;
+                                changed = true;
+                            }
+                        } else {
+                            let new_hi = floor_div(bound, a);
+                            if new_hi < hi[var] {
+                                hi[var] = new_hi;
+                                changed = true;
+                            }
+                        }
+                    }
+                    if lo[var] > hi[var] {
+                        return false;
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        true
+    }
+
+    /// For `expr != 0` with a single variable left unfixed, trims the value that would make
+    /// the expression zero off that variable's domain if it sits on a bound.
+    fn exclude_root(constraint: &Constraint, lo: &mut [i128], hi: &mut [i128]) -> bool {
+        let mut open = constraint
+            .expr
+            .terms
+            .iter()
+            .filter(|(v, _)| lo[**v] < hi[**v]);
+        let (var, coefficient) = match (open.next(), open.next()) {
+            (Some((&var, &coefficient)), None) => (var, coefficient as i128),
+            _ => return false,
+        };
+        let rest: i128 = constraint
+            .expr
+            .terms
+            .iter()
+            .filter(|(v, _)| **v != var)
+            .map(|(v, c)| *c as i128 * lo[*v])
+            .sum::<i128>()
+            + constraint.expr.constant as i128;
+        if rest % coefficient != 0 {
+            return false;
+        }
+        let root = -rest / coefficient;
+        if root == lo[var] {
+            lo[var] += 1;
+            true
+        } else if root == hi[var] {
+            hi[var] -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn search(&mut self, mut lo: Vec<i128>, mut hi: Vec<i128>) -> Option<Vec<isize>> {
+        self.nodes += 1;
+        if self.nodes > self.max_nodes || !self.propagate(&mut lo, &mut hi) {
+            return None;
+        }
+
+        let open = (0..lo.len())
+            .filter(|&var| lo[var] < hi[var])
+            .min_by_key(|&var| hi[var] - lo[var]);
+        let var = match open {
+            Some(var) => var,
+            None => {
+                let assignment: Vec<isize> = lo.iter().map(|&v| v as isize).collect();
+                return if self.constraints.iter().all(|c| c.holds(&assignment)) {
+                    Some(assignment)
+                } else {
+                    None
+                };
+            }
+        };
+
+        // Try the hinted value first so solutions stay close to the previous run, then
+        // bisect whatever is left.
+        let hint = self.hint.get(var).map_or(0, |&h| h as i128);
+        let parts = if lo[var] <= hint && hint <= hi[var] {
+            vec![(hint, hint), (lo[var], hint - 1), (hint + 1, hi[var])]
+        } else {
+            let mid = lo[var] + (hi[var] - lo[var]) / 2;
+            vec![(lo[var], mid), (mid + 1, hi[var])]
+        };
+        for (part_lo, part_hi) in parts {
+            if part_lo > part_hi {
+                continue;
+            }
+            let (mut lo, mut hi) = (lo.clone(), hi.clone());
+            lo[var] = part_lo;
+            hi[var] = part_hi;
+            if let Some(solution) = self.search(lo, hi) {
+                return Some(solution);
+            }
+        }
+        None
+    }
+}
+
+/// Finds values within `domains` satisfying every constraint, preferring values from `hint`.
+/// Gives up with `None` after visiting `max_nodes` search nodes, so `None` means "no solution
+/// found" rather than "unsatisfiable".
+pub fn solve(
+    constraints: &[Constraint],
+    domains: &[RangeInclusive<isize>],
+    hint: &[isize],
+    max_nodes: usize,
+) -> Option<Vec<isize>> {
+    let lo = domains.iter().map(|d| *d.start() as i128).collect();
+    let hi = domains.iter().map(|d| *d.end() as i128).collect();
+    let mut search = Search {
+        constraints,
+        hint,
+        nodes: 0,
+        max_nodes,
+    };
+    search.search(lo, hi)
+}
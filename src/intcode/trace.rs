@@ -0,0 +1,99 @@
+use super::*;
+use std::io::Write;
+
+/// Hook that writes a line per executed instruction, optionally followed by its memory and
+/// I/O effects, e.g. `IntcodeMachine::new(tape).with_hook(Tracer::new(std::io::stderr()))`.
+#[derive(Debug)]
+pub struct Tracer<W: Write> {
+    out: W,
+    effects: bool,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(out: W) -> Self {
+        Tracer { out, effects: true }
+    }
+
+    /// Only trace instructions, not the reads, writes and I/O they perform.
+    pub fn instructions_only(out: W) -> Self {
+        Tracer {
+            out,
+            effects: false,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn effect(&mut self, args: fmt::Arguments) {
+        if self.effects {
+            // Tracing is best-effort; a broken sink shouldn't stop the machine.
+            let _ = writeln!(self.out, "{:>10}{}", "", args);
+        }
+    }
+}
+
+impl<W: Write> IntcodeHook for Tracer<W> {
+    fn on_instruction(&mut self, pc: isize, opcode: &Opcode) {
+        let _ = writeln!(self.out, "{:>6}  {}", pc, opcode);
+    }
+
+    fn on_read(&mut self, addr: usize, value: isize) {
+        self.effect(format_args!("read  [{}] = {}", addr, value));
+    }
+
+    fn on_write(&mut self, addr: usize, old: isize, new: isize) {
+        self.effect(format_args!("write [{}] = {} (was {})", addr, new, old));
+    }
+
+    fn on_input(&mut self, value: isize) {
+        self.effect(format_args!("input  {}", value));
+    }
+
+    fn on_output(&mut self, value: isize) {
+        self.effect(format_args!("output {}", value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace(tracer: Tracer<Vec<u8>>, program: &str, inputs: &[isize]) -> String {
+        let mut machine = IntcodeMachine::new(parse_intcode_program(program)).with_hook(tracer);
+        machine.input.borrow_mut().extend(inputs);
+        assert_eq!(machine.run(), Ok(StopStatus::Halted));
+        String::from_utf8(machine.hook.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn test_trace_with_effects() {
+        let expected = "     0  IN [9]
+          input  5
+          write [9] = 5 (was 0)
+     2  ADD [9], #1, [9]
+          read  [9] = 5
+          write [9] = 6 (was 5)
+     6  OUT [9]
+          read  [9] = 6
+          output 6
+     8  HLT
+";
+        let text = trace(Tracer::new(Vec::new()), "3,9,1001,9,1,9,4,9,99", &[5]);
+        assert_eq!(text, expected);
+    }
+
+    #[test]
+    fn test_trace_instructions_only() {
+        let text = trace(
+            Tracer::instructions_only(Vec::new()),
+            "3,9,1001,9,1,9,4,9,99",
+            &[5],
+        );
+        assert_eq!(
+            text,
+            "     0  IN [9]\n     2  ADD [9], #1, [9]\n     6  OUT [9]\n     8  HLT\n"
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! `IntcodeMachine` with `i128` words, for programs whose values outgrow an `isize`.
+//! Addresses, including the base pointer, must still fit in an `isize`; one that doesn't
+//! faults with `IntcodeError::Overflow`.
+
+use super::format::{parse_words, ParseError};
+use super::*;
+
+pub type WideWord = i128;
+pub type WideTape = Vec<WideWord>;
+pub type WideStream = VecDeque<WideWord>;
+pub type WideStreamRef = Rc<RefCell<WideStream>>;
+pub type WideMachine<I = WideStreamRef, O = WideStreamRef, H = NoHook> =
+    IntcodeMachine<I, O, H, WideWord>;
+
+pub fn try_parse_wide_program(input: &str) -> Result<WideTape, ParseError> {
+    parse_words(input)
+}
+
+/// Like `try_parse_wide_program`, but panics on malformed input.
+pub fn parse_wide_program(input: &str) -> WideTape {
+    try_parse_wide_program(input).unwrap_or_else(|e| panic!("Invalid Intcode program: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(program: &str, arithmetic: Arithmetic) -> IntcodeResult<Vec<WideWord>> {
+        let mut machine = WideMachine::new(parse_wide_program(program));
+        machine.set_arithmetic(arithmetic);
+        machine.run_to_completion()?;
+        let outputs = machine.output.borrow().iter().copied().collect();
+        Ok(outputs)
+    }
+
+    #[test]
+    fn test_day9_examples() {
+        let quine = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+        assert_eq!(
+            run(quine, Arithmetic::Checked),
+            Ok(parse_wide_program(quine))
+        );
+        assert_eq!(
+            run("1102,34915192,34915192,7,4,7,99,0", Arithmetic::Checked),
+            Ok(vec![1219070632396864])
+        );
+    }
+
+    #[test]
+    fn test_values_wider_than_isize() {
+        let program = format!("1102,{},4,7,4,7,99,0", 1isize << 62);
+        assert_eq!(run(&program, Arithmetic::Checked), Ok(vec![1 << 64]));
+        let program = format!("104,{},99", i128::MIN);
+        assert_eq!(run(&program, Arithmetic::Checked), Ok(vec![i128::MIN]));
+
+        let narrow = IntcodeMachine::new(parse_intcode_program(&format!(
+            "1102,{},4,7,4,7,99,0",
+            1isize << 62
+        )))
+        .run();
+        assert_eq!(
+            narrow,
+            Err(IntcodeError::Overflow {
+                pc: 0,
+                opcode: 1102,
+            })
+        );
+    }
+
+    #[test]
+    fn test_overflow_in_each_mode() {
+        let overflow = Err(IntcodeError::Overflow {
+            pc: 0,
+            opcode: 1101,
+        });
+        let add = format!("1101,{},1,0,4,0,99", i128::MAX);
+        assert_eq!(run(&add, Arithmetic::Checked), overflow);
+        assert_eq!(run(&add, Arithmetic::Wrapping), Ok(vec![i128::MIN]));
+
+        let overflow = Err(IntcodeError::Overflow {
+            pc: 0,
+            opcode: 1102,
+        });
+        let mul = format!("1102,{},2,0,4,0,99", 1i128 << 126);
+        assert_eq!(run(&mul, Arithmetic::Checked), overflow);
+        assert_eq!(run(&mul, Arithmetic::Wrapping), Ok(vec![i128::MIN]));
+    }
+
+    #[test]
+    fn test_base_pointer_overflow() {
+        // Past i128::MAX the checked sum fails, and the wrapped one goes negative.
+        let arb = format!("109,{},109,{},99", isize::MAX, i128::MAX);
+        assert_eq!(
+            run(&arb, Arithmetic::Checked),
+            Err(IntcodeError::Overflow { pc: 2, opcode: 109 })
+        );
+        assert_eq!(
+            run(&arb, Arithmetic::Wrapping),
+            Err(IntcodeError::NegativeAddress {
+                pc: 2,
+                opcode: Some(109),
+                operand: Some(0),
+                addr: isize::MIN,
+            })
+        );
+        // The sum fits in a word but not in an address.
+        let arb = format!("109,{},109,1,99", isize::MAX);
+        assert_eq!(
+            run(&arb, Arithmetic::Wrapping),
+            Err(IntcodeError::Overflow { pc: 2, opcode: 109 })
+        );
+    }
+
+    #[test]
+    fn test_blocks_on_input() {
+        let mut machine = WideMachine::new(parse_wide_program("3,0,4,0,99"));
+        assert_eq!(machine.run(), Ok(StopStatus::BlockedOnInput));
+        assert_eq!(machine.pc(), 0);
+        machine.input.borrow_mut().push_back(1 << 100);
+        assert_eq!(machine.run(), Ok(StopStatus::Halted));
+        assert_eq!(machine.output.borrow().front(), Some(&(1 << 100)));
+        assert_eq!(machine.instruction_count(), 2);
+    }
+
+    #[test]
+    fn test_extensions_and_forks() {
+        let mut registry = ExtensionRegistry::<WideWord>::default();
+        registry.register(60, "MAX", 3, true, |call| {
+            Ok(ExtensionAction::Store(call.args[0].max(call.args[1])))
+        });
+        // IN [0]; MAX [0], #2^100, [0]; OUT [0]; HLT
+        let program = format!("3,0,1060,0,{},0,4,0,99", 1i128 << 100);
+        let mut machine = WideMachine::new(parse_wide_program(&program));
+        machine.set_extensions(Some(Arc::new(registry)));
+        assert_eq!(machine.run(), Ok(StopStatus::BlockedOnInput));
+
+        let mut fork = machine.fork();
+        machine.input.borrow_mut().push_back(1);
+        fork.input.borrow_mut().push_back(1 << 101);
+        assert_eq!(machine.run(), Ok(StopStatus::Halted));
+        assert_eq!(fork.run(), Ok(StopStatus::Halted));
+        assert_eq!(machine.output.borrow().front(), Some(&(1 << 100)));
+        assert_eq!(fork.output.borrow().front(), Some(&(1 << 101)));
+    }
+
+    #[test]
+    fn test_invalid_opcode_words_are_saturated() {
+        let program = format!("{},99", i128::MIN);
+        assert_eq!(
+            run(&program, Arithmetic::Checked),
+            Err(IntcodeError::NegativeOpcode {
+                pc: 0,
+                opcode: isize::MIN,
+            })
+        );
+    }
+}
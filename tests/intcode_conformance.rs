@@ -0,0 +1,346 @@
+//! Conformance tests for the Intcode VM, built from the example programs published with
+//! days 2, 5 and 9, plus one small program per `IntcodeError` variant.
+
+use aoc2019::intcode::extensions::standard_extensions;
+use aoc2019::intcode::*;
+use std::sync::Arc;
+
+struct Run {
+    status: StopStatus,
+    outputs: Vec<isize>,
+    memory: Vec<isize>,
+}
+
+fn machine(program: &str, inputs: &[isize]) -> IntcodeMachine {
+    let machine = IntcodeMachine::new(parse_intcode_program(program));
+    machine.input.borrow_mut().extend(inputs);
+    machine
+}
+
+/// Runs `program` with the decode cache on and off, checking both agree.
+fn run(program: &str, inputs: &[isize]) -> Run {
+    let mut runs = [true, false].iter().map(|&cache| {
+        let mut machine = machine(program, inputs);
+        machine.set_decode_cache(cache);
+        let status = machine.run().expect("program faulted");
+        let outputs = machine.output.borrow().iter().copied().collect();
+        Run {
+            status,
+            outputs,
+            memory: machine.memory().dense().to_vec(),
+        }
+    });
+    let (cached, uncached) = (runs.next().unwrap(), runs.next().unwrap());
+    assert_eq!(cached.status, uncached.status);
+    assert_eq!(cached.outputs, uncached.outputs);
+    assert_eq!(cached.memory, uncached.memory);
+    cached
+}
+
+fn outputs(program: &str, inputs: &[isize]) -> Vec<isize> {
+    let run = run(program, inputs);
+    assert_eq!(run.status, StopStatus::Halted);
+    run.outputs
+}
+
+fn final_memory(program: &str) -> Vec<isize> {
+    let run = run(program, &[]);
+    assert_eq!(run.status, StopStatus::Halted);
+    run.memory
+}
+
+fn fault(program: &str, inputs: &[isize]) -> IntcodeError {
+    machine(program, inputs)
+        .run()
+        .expect_err("program should fault")
+}
+
+#[test]
+fn day2_add_and_multiply() {
+    let cases: &[(&str, &[isize])] = &[
+        (
+            "1,9,10,3,2,3,11,0,99,30,40,50",
+            &[3500, 9, 10, 70, 2, 3, 11, 0, 99, 30, 40, 50],
+        ),
+        ("1,0,0,0,99", &[2, 0, 0, 0, 99]),
+        ("2,3,0,3,99", &[2, 3, 0, 6, 99]),
+        ("2,4,4,5,99,0", &[2, 4, 4, 5, 99, 9801]),
+        ("1,1,1,4,99,5,6,0,99", &[30, 1, 1, 4, 2, 5, 6, 0, 99]),
+    ];
+    for (program, expected) in cases {
+        assert_eq!(&final_memory(program)[..expected.len()], *expected);
+    }
+}
+
+#[test]
+fn day5_input_and_output() {
+    assert_eq!(outputs("3,0,4,0,99", &[-42]), vec![-42]);
+}
+
+#[test]
+fn day5_immediate_mode() {
+    assert_eq!(final_memory("1002,4,3,4,33")[4], 99);
+    assert_eq!(final_memory("1101,100,-1,4,0")[4], 99);
+}
+
+#[test]
+fn day5_comparisons() {
+    let equals_8 = ["3,9,8,9,10,9,4,9,99,-1,8", "3,3,1108,-1,8,3,4,3,99"];
+    let less_than_8 = ["3,9,7,9,10,9,4,9,99,-1,8", "3,3,1107,-1,8,3,4,3,99"];
+    for input in 6..=10 {
+        for program in &equals_8 {
+            assert_eq!(outputs(program, &[input]), vec![(input == 8) as isize]);
+        }
+        for program in &less_than_8 {
+            assert_eq!(outputs(program, &[input]), vec![(input < 8) as isize]);
+        }
+    }
+}
+
+#[test]
+fn day5_jumps() {
+    let programs = [
+        "3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9",
+        "3,3,1105,-1,9,1101,0,0,12,4,12,99,1",
+    ];
+    for program in &programs {
+        assert_eq!(outputs(program, &[0]), vec![0]);
+        assert_eq!(outputs(program, &[-3]), vec![1]);
+    }
+
+    let around_8 = "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,\
+                    1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,\
+                    999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99";
+    assert_eq!(outputs(around_8, &[7]), vec![999]);
+    assert_eq!(outputs(around_8, &[8]), vec![1000]);
+    assert_eq!(outputs(around_8, &[9]), vec![1001]);
+}
+
+#[test]
+fn day9_quine() {
+    let program = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+    assert_eq!(outputs(program, &[]), parse_intcode_program(program));
+}
+
+#[test]
+fn day9_large_numbers() {
+    assert_eq!(
+        outputs("1102,34915192,34915192,7,4,7,99,0", &[]),
+        vec![1219070632396864]
+    );
+    assert_eq!(
+        outputs("104,1125899906842624,99", &[]),
+        vec![1125899906842624]
+    );
+}
+
+#[test]
+fn relative_mode_in_every_position() {
+    // Reads into bp + 0, adds it to itself into bp + 1 and prints bp + 1.
+    assert_eq!(
+        outputs("109,20,203,0,22201,0,0,1,204,1,99", &[21]),
+        vec![42]
+    );
+    // Relative jumps and comparisons: 5 < 6, so jump to the output of 1.
+    assert_eq!(
+        outputs(
+            "109,40,21101,5,0,0,21207,0,6,1,1205,1,21,104,0,99,0,0,0,0,0,104,1,99",
+            &[]
+        ),
+        vec![1]
+    );
+}
+
+#[test]
+fn memory_past_the_program_reads_as_zero() {
+    assert_eq!(
+        outputs("4,1000000,1101,7,0,1000000,4,1000000,99", &[]),
+        vec![0, 7]
+    );
+}
+
+#[test]
+fn blocks_on_input_and_resumes() {
+    let mut machine = machine("3,0,4,0,3,0,4,0,99", &[1]);
+    assert_eq!(machine.run(), Ok(StopStatus::BlockedOnInput));
+    assert_eq!(machine.pc(), 4);
+    machine.input.borrow_mut().push_back(2);
+    assert_eq!(machine.run(), Ok(StopStatus::Halted));
+    assert_eq!(machine.pc(), 8);
+    assert_eq!(machine.output.borrow().iter().collect::<Vec<_>>(), [&1, &2]);
+    assert_eq!(machine.instruction_count(), 4);
+}
+
+#[test]
+fn budget_and_output_yields() {
+    let mut machine = machine("104,1,104,2,104,3,99", &[]);
+    assert_eq!(machine.run_for(2), Ok(StopStatus::BudgetExhausted));
+    assert_eq!(machine.pc(), 4);
+    machine.yield_on_output(Some(1));
+    assert_eq!(machine.run(), Ok(StopStatus::OutputReady));
+    assert_eq!(machine.pc(), 6);
+    assert_eq!(machine.run(), Ok(StopStatus::Halted));
+}
+
+#[test]
+fn self_modifying_code() {
+    // The first pass rewrites the `add` at 0 into a `mul`, so the loop body changes
+    // between iterations even with the decode cache on.
+    let program = "1101,3,3,30,4,30,1005,31,20,1101,0,1,31,1101,0,1102,0,1105,1,0,99";
+    assert_eq!(outputs(program, &[]), vec![6, 9]);
+}
+
+#[test]
+fn error_invalid_opcode_operation() {
+    assert_eq!(
+        fault("1101,1,1,0,42", &[]),
+        IntcodeError::InvalidOpcodeOperation { pc: 4, opcode: 42 }
+    );
+}
+
+#[test]
+fn error_negative_opcode() {
+    assert_eq!(
+        fault("-1", &[]),
+        IntcodeError::NegativeOpcode { pc: 0, opcode: -1 }
+    );
+}
+
+#[test]
+fn error_invalid_addressing_mode() {
+    assert_eq!(
+        fault("3001,0,0,0,99", &[]),
+        IntcodeError::InvalidAddressingMode {
+            pc: 0,
+            opcode: 3001,
+            operand: 1,
+            mode: 3,
+        }
+    );
+    assert_eq!(
+        fault("100099", &[]),
+        IntcodeError::InvalidAddressingMode {
+            pc: 0,
+            opcode: 100099,
+            operand: 3,
+            mode: 1,
+        }
+    );
+}
+
+#[test]
+fn error_negative_address() {
+    assert_eq!(
+        fault("1,0,-5,0,99", &[]),
+        IntcodeError::NegativeAddress {
+            pc: 0,
+            opcode: Some(1),
+            operand: Some(1),
+            addr: -5,
+        }
+    );
+    assert_eq!(
+        fault("109,-1,99", &[]),
+        IntcodeError::NegativeAddress {
+            pc: 0,
+            opcode: Some(109),
+            operand: Some(0),
+            addr: -1,
+        }
+    );
+    assert_eq!(
+        fault("1105,1,-2", &[]),
+        IntcodeError::NegativeAddress {
+            pc: 0,
+            opcode: Some(1105),
+            operand: Some(1),
+            addr: -2,
+        }
+    );
+    assert_eq!(
+        machine("99", &[]).read_addr(-1),
+        Err(IntcodeError::NegativeAddress {
+            pc: 0,
+            opcode: None,
+            operand: None,
+            addr: -1,
+        })
+    );
+}
+
+#[test]
+fn error_invalid_store_addressing_mode() {
+    assert_eq!(
+        fault("3,0,11101,1,1,0,99", &[5]),
+        IntcodeError::InvalidStoreAddressingMode {
+            pc: 2,
+            opcode: 11101,
+            operand: 2,
+        }
+    );
+}
+
+#[test]
+fn error_did_not_run_to_completion() {
+    assert_eq!(
+        machine("4,0,3,0,99", &[]).run_to_completion(),
+        Err(IntcodeError::DidNotRunToCompletion {
+            pc: 2,
+            status: StopStatus::BlockedOnInput,
+        })
+    );
+}
+
+#[test]
+fn error_overflow() {
+    let program = format!("1101,{},1,0,99", isize::MAX);
+    assert_eq!(
+        fault(&program, &[]),
+        IntcodeError::Overflow {
+            pc: 0,
+            opcode: 1101,
+        }
+    );
+
+    let mut wrapping = machine(&program, &[]);
+    wrapping.set_arithmetic(Arithmetic::Wrapping);
+    assert_eq!(wrapping.run(), Ok(StopStatus::Halted));
+    assert_eq!(wrapping.memory().get(0), isize::MIN);
+}
+
+#[test]
+fn error_extension_failed() {
+    let mut machine = machine("1151,1,2,99", &[]);
+    machine.set_extensions(Some(Arc::new(standard_extensions())));
+    assert_eq!(
+        machine.run(),
+        Err(IntcodeError::ExtensionFailed {
+            pc: 0,
+            opcode: 1151,
+            message: "assertion failed: 1 != 2".to_string(),
+        })
+    );
+}
+
+#[test]
+fn error_memory_limit_exceeded() {
+    let mut machine = machine("1101,1,1,1000000000,99", &[]);
+    machine.set_memory_limit(Some(1000));
+    assert_eq!(
+        machine.run(),
+        Err(IntcodeError::MemoryLimitExceeded {
+            pc: 0,
+            addr: 1000000000,
+            limit: 1000,
+        })
+    );
+}
+
+#[test]
+fn faults_leave_the_machine_on_the_instruction() {
+    let mut machine = machine("104,7,1,0,-5,0,99", &[]);
+    assert!(machine.run().is_err());
+    assert_eq!(machine.pc(), 2);
+    assert_eq!(machine.instruction_count(), 1);
+    assert!(machine.run().is_err());
+}
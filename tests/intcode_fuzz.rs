@@ -0,0 +1,425 @@
+//! Property tests for the Intcode VM on random tapes. Every tape runs in lockstep with a
+//! deliberately naive reference interpreter, and every tick has to agree on the result,
+//! the pc and the base pointer; at the end memory and output have to agree too. A panic
+//! anywhere in the VM fails the test with the seed that reproduces it.
+//!
+//! `INTCODE_FUZZ_CASES` and `INTCODE_FUZZ_SEED` override the number of tapes and the seed.
+
+use aoc2019::intcode::*;
+use std::collections::{BTreeMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+
+const DEFAULT_CASES: u64 = 3000;
+const DEFAULT_SEED: u64 = 0x2019_c0de_5eed;
+/// Instructions per tape, so that random loops still finish.
+const BUDGET: usize = 400;
+const MEMORY_LIMIT: usize = 16 * 1024;
+
+/// xorshift64*, which is plenty for picking opcodes.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        self.below(100) < percent
+    }
+
+    fn range(&mut self, lo: isize, hi: isize) -> isize {
+        lo + self.below((hi - lo + 1) as u64) as isize
+    }
+}
+
+/// Operation codes and their operand counts.
+const OPERATIONS: [(isize, usize); 10] = [
+    (1, 3),
+    (2, 3),
+    (3, 1),
+    (4, 1),
+    (5, 2),
+    (6, 2),
+    (7, 3),
+    (8, 3),
+    (9, 1),
+    (99, 0),
+];
+
+/// Mostly addresses in the scratch area just past a tape of `len` words, so that stores
+/// don't often overwrite the code. With `noise` percent chance it's something nastier.
+fn random_value(rng: &mut Rng, len: usize, noise: u64) -> isize {
+    let len = len as isize;
+    if rng.chance(noise) {
+        return match rng.below(3) {
+            0 => [isize::MAX, isize::MIN, isize::MAX - 1, isize::MIN + 1][rng.below(4) as usize],
+            1 => rng.range(-1_000_000_000, 1_000_000_000),
+            _ => rng.range(-5, -1),
+        };
+    }
+    if rng.chance(15) {
+        rng.range(0, len - 1)
+    } else {
+        rng.range(len, len + 16)
+    }
+}
+
+/// An opcode word and its operand count.
+fn random_opcode(rng: &mut Rng, noise: u64) -> (isize, usize) {
+    // Halting is rare so that runs get somewhere.
+    let (code, operands) = match rng.chance(3) {
+        true => OPERATIONS[9],
+        false => OPERATIONS[rng.below(9) as usize],
+    };
+    let stores = matches!(code, 1 | 2 | 3 | 7 | 8);
+    let mut word = code;
+    let mut scale = 100;
+    for i in 0..operands {
+        let mode = if rng.chance(noise / 4) {
+            rng.range(3, 9)
+        } else if stores && i == operands - 1 && !rng.chance(3) {
+            [0, 2][rng.below(2) as usize]
+        } else {
+            rng.range(0, 2)
+        };
+        word += mode * scale;
+        scale *= 10;
+    }
+    if !rng.chance(noise) {
+        return (word, operands);
+    }
+    let word = match rng.below(3) {
+        0 => -word,
+        1 => word + 100_000 * rng.range(1, 9),
+        _ => rng.range(10, 98),
+    };
+    (word, operands)
+}
+
+/// Well-formed instructions, except that with `noise` percent chance a word is garbage or an
+/// opcode or operand is bad. Immediate jump targets usually point back at an earlier
+/// instruction, making loops.
+fn random_tape(rng: &mut Rng, noise: u64) -> Tape {
+    let len = rng.range(1, 256) as usize;
+    let mut tape = Tape::new();
+    let mut starts = vec![0];
+    while tape.len() < len {
+        if rng.chance(noise) {
+            tape.push(random_value(rng, len, 100));
+            continue;
+        }
+        let (word, operands) = random_opcode(rng, noise);
+        starts.push(tape.len());
+        tape.push(word);
+        for i in 0..operands {
+            let jump_target = matches!(word, 1105 | 1106 | 105 | 106 | 1205 | 1206) && i == 1;
+            if jump_target && rng.chance(80) {
+                tape.push(starts[rng.below(starts.len() as u64) as usize] as isize);
+            } else {
+                tape.push(random_value(rng, len, noise));
+            }
+        }
+    }
+    tape
+}
+
+/// A second interpreter written straight from the puzzle text, with sparse memory and no
+/// decode cache. Its faults follow the VM's rules: decode errors first, then operands in
+/// order, and the store last.
+struct Reference {
+    memory: BTreeMap<usize, isize>,
+    pc: isize,
+    bp: isize,
+    input: VecDeque<isize>,
+    output: Vec<isize>,
+    arithmetic: Arithmetic,
+    /// Address written by the last instruction, if any.
+    last_write: Option<usize>,
+}
+
+impl Reference {
+    fn new(tape: &[isize], input: &[isize], arithmetic: Arithmetic) -> Self {
+        Reference {
+            memory: tape.iter().copied().enumerate().collect(),
+            pc: 0,
+            bp: 0,
+            input: input.iter().copied().collect(),
+            output: Vec::new(),
+            arithmetic,
+            last_write: None,
+        }
+    }
+
+    fn get(&self, addr: usize) -> isize {
+        self.memory.get(&addr).copied().unwrap_or(0)
+    }
+
+    fn add(&self, a: isize, b: isize, pc: isize, word: isize) -> IntcodeResult<isize> {
+        match self.arithmetic {
+            Arithmetic::Checked => a
+                .checked_add(b)
+                .ok_or(IntcodeError::Overflow { pc, opcode: word }),
+            Arithmetic::Wrapping => Ok(a.wrapping_add(b)),
+        }
+    }
+
+    fn mul(&self, a: isize, b: isize, pc: isize, word: isize) -> IntcodeResult<isize> {
+        match self.arithmetic {
+            Arithmetic::Checked => a
+                .checked_mul(b)
+                .ok_or(IntcodeError::Overflow { pc, opcode: word }),
+            Arithmetic::Wrapping => Ok(a.wrapping_mul(b)),
+        }
+    }
+
+    fn step(&mut self) -> IntcodeResult<Option<StopStatus>> {
+        let start = self.pc;
+        self.last_write = None;
+        let result = self.execute();
+        if result.is_err() {
+            self.pc = start;
+        }
+        result
+    }
+
+    fn execute(&mut self) -> IntcodeResult<Option<StopStatus>> {
+        let pc = self.pc;
+        let word = self.get(pc as usize);
+        if word < 0 {
+            return Err(IntcodeError::NegativeOpcode { pc, opcode: word });
+        }
+        if word >= 100_000 {
+            return Err(IntcodeError::InvalidAddressingMode {
+                pc,
+                opcode: word,
+                operand: 3,
+                mode: (word / 100_000) as usize,
+            });
+        }
+        let code = word % 100;
+        let count = match OPERATIONS.iter().find(|(c, _)| *c == code) {
+            Some(&(_, count)) => count,
+            None => return Err(IntcodeError::InvalidOpcodeOperation { pc, opcode: word }),
+        };
+        let mut operands = Vec::new();
+        for i in 0..count {
+            let mode = (word as usize / 10usize.pow(i as u32 + 2)) % 10;
+            if mode > 2 {
+                return Err(IntcodeError::InvalidAddressingMode {
+                    pc,
+                    opcode: word,
+                    operand: i,
+                    mode,
+                });
+            }
+            operands.push((mode, self.get(pc as usize + 1 + i)));
+        }
+
+        let negative = |operand: usize, addr: isize| IntcodeError::NegativeAddress {
+            pc,
+            opcode: Some(word),
+            operand: Some(operand),
+            addr,
+        };
+        let load = |this: &Self, i: usize| -> IntcodeResult<isize> {
+            let (mode, value) = operands[i];
+            let addr = match mode {
+                0 => value,
+                1 => return Ok(value),
+                _ => this.add(this.bp, value, pc, word)?,
+            };
+            if addr < 0 {
+                return Err(negative(i, addr));
+            }
+            Ok(this.get(addr as usize))
+        };
+        let store = |this: &mut Self, i: usize, result: isize| -> IntcodeResult<()> {
+            let (mode, value) = operands[i];
+            let addr = match mode {
+                0 => value,
+                1 => {
+                    return Err(IntcodeError::InvalidStoreAddressingMode {
+                        pc,
+                        opcode: word,
+                        operand: i,
+                    })
+                }
+                _ => this.add(this.bp, value, pc, word)?,
+            };
+            if addr < 0 {
+                return Err(negative(i, addr));
+            }
+            this.memory.insert(addr as usize, result);
+            this.last_write = Some(addr as usize);
+            Ok(())
+        };
+
+        self.pc = pc
+            .checked_add(count as isize + 1)
+            .ok_or(IntcodeError::Overflow { pc, opcode: word })?;
+        match code {
+            1 => {
+                let (a, b) = (load(self, 0)?, load(self, 1)?);
+                let result = self.add(a, b, pc, word)?;
+                store(self, 2, result)?;
+            }
+            2 => {
+                let (a, b) = (load(self, 0)?, load(self, 1)?);
+                let result = self.mul(a, b, pc, word)?;
+                store(self, 2, result)?;
+            }
+            3 => match self.input.pop_front() {
+                Some(value) => store(self, 0, value)?,
+                None => {
+                    self.pc = pc;
+                    return Ok(Some(StopStatus::BlockedOnInput));
+                }
+            },
+            4 => {
+                let value = load(self, 0)?;
+                self.output.push(value);
+            }
+            5 | 6 => {
+                let condition = load(self, 0)?;
+                let target = load(self, 1)?;
+                if (condition != 0) == (code == 5) {
+                    if target < 0 {
+                        return Err(negative(1, target));
+                    }
+                    self.pc = target;
+                }
+            }
+            7 => {
+                let result = load(self, 0)? < load(self, 1)?;
+                store(self, 2, result as isize)?;
+            }
+            8 => {
+                let result = load(self, 0)? == load(self, 1)?;
+                store(self, 2, result as isize)?;
+            }
+            9 => {
+                let offset = load(self, 0)?;
+                let bp = self.add(self.bp, offset, pc, word)?;
+                if bp < 0 {
+                    return Err(negative(0, bp));
+                }
+                self.bp = bp;
+            }
+            _ => {
+                self.pc = pc;
+                return Ok(Some(StopStatus::Halted));
+            }
+        }
+        Ok(None)
+    }
+}
+
+struct Case {
+    tape: Tape,
+    input: Vec<isize>,
+    arithmetic: Arithmetic,
+    decode_cache: bool,
+    memory_limit: Option<usize>,
+}
+
+fn random_case(rng: &mut Rng) -> Case {
+    let noise = rng.below(11);
+    let tape = random_tape(rng, noise);
+    let input = (0..rng.below(8))
+        .map(|_| random_value(rng, tape.len(), noise))
+        .collect();
+    Case {
+        tape,
+        input,
+        arithmetic: if rng.chance(50) {
+            Arithmetic::Checked
+        } else {
+            Arithmetic::Wrapping
+        },
+        decode_cache: rng.chance(50),
+        memory_limit: if rng.chance(50) {
+            Some(MEMORY_LIMIT)
+        } else {
+            None
+        },
+    }
+}
+
+/// Runs the case on both interpreters, panicking on the first disagreement.
+fn check(case: &Case) {
+    let mut machine = IntcodeMachine::new(case.tape.clone());
+    machine.input.borrow_mut().extend(&case.input);
+    machine.set_arithmetic(case.arithmetic);
+    machine.set_decode_cache(case.decode_cache);
+    machine.set_memory_limit(case.memory_limit);
+    let mut reference = Reference::new(&case.tape, &case.input, case.arithmetic);
+
+    for tick in 0..BUDGET {
+        let actual = machine.tick();
+        let expected = reference.step();
+        if let (Err(IntcodeError::MemoryLimitExceeded { addr, .. }), Ok(None)) =
+            (&actual, &expected)
+        {
+            // The reference has no limit; it must at least have written where the VM ran
+            // out of room.
+            assert_eq!(reference.last_write, Some(*addr), "tick {}", tick);
+            return;
+        }
+        assert_eq!(actual, expected, "tick {}", tick);
+        assert_eq!(machine.pc(), reference.pc, "pc after tick {}", tick);
+        assert_eq!(machine.bp(), reference.bp, "bp after tick {}", tick);
+        if actual != Ok(None) {
+            break;
+        }
+    }
+
+    let output: Vec<isize> = machine.output.borrow().iter().copied().collect();
+    assert_eq!(output, reference.output);
+    for (&addr, &value) in &reference.memory {
+        assert_eq!(machine.memory().get(addr), value, "memory at {}", addr);
+    }
+    for (addr, &value) in machine.memory().dense().iter().enumerate() {
+        assert_eq!(reference.get(addr), value, "memory at {}", addr);
+    }
+}
+
+fn env_u64(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+#[test]
+fn random_tapes_match_the_reference_interpreter() {
+    let cases = env_u64("INTCODE_FUZZ_CASES", DEFAULT_CASES);
+    let seed = env_u64("INTCODE_FUZZ_SEED", DEFAULT_SEED);
+    let mut rng = Rng::new(seed);
+    for index in 0..cases {
+        let case = random_case(&mut rng);
+        if panic::catch_unwind(AssertUnwindSafe(|| check(&case))).is_err() {
+            panic!(
+                "case {} of seed {} failed: tape {:?}, input {:?}, {:?}, decode cache {}, \
+                 memory limit {:?}",
+                index,
+                seed,
+                case.tape,
+                case.input,
+                case.arithmetic,
+                case.decode_cache,
+                case.memory_limit
+            );
+        }
+    }
+}